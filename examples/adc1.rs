@@ -15,10 +15,11 @@ extern crate cortex_m_debug;
 extern crate cortex_m_rtfm as rtfm;
 extern crate stm32_f429_bgt6;
 
-use stm32_f429_bgt6::adc::{Adc, AdcChannel};
+use stm32_f429_bgt6::adc::{Adc, AdcChannel, Edge, Trigger};
 use stm32_f429_bgt6::dma::{CircBuffer, Dma2Stream0};
 use stm32_f429_bgt6::time::Hertz;
 use stm32_f429_bgt6::{Channel, Pwm};
+use stm32_f429_bgt6::prelude::*;
 use stm32_f429_bgt6::led::{self, LED};
 use rtfm::{app, Threshold};
 
@@ -58,9 +59,13 @@ fn init(p: init::Peripherals, r: init::Resources) {
         p.RCC,
     );
 
+    // The CC2 event triggers the conversions, PA1 is taken over by the ADC
+    pwm.set_duty(Channel::_2, 1);
+    pwm.enable(Channel::_2);
+
     let adc = Adc(p.ADC1);
 
-    adc.init(p.DMA2, p.RCC);
+    adc.init(Trigger::Tim2Cc2, Edge::Rising, p.RCC);
     adc.enable_input(AdcChannel::_0, 1, p.GPIOA, p.GPIOB, p.GPIOC);
     adc.enable_input(AdcChannel::_1, 2, p.GPIOA, p.GPIOB, p.GPIOC);
     adc.enable_input(AdcChannel::_4, 3, p.GPIOA, p.GPIOB, p.GPIOC);
@@ -68,7 +73,7 @@ fn init(p: init::Peripherals, r: init::Resources) {
     adc.enable_input(AdcChannel::_11, 5, p.GPIOA, p.GPIOB, p.GPIOC);
     adc.enable_input(AdcChannel::_10, 6, p.GPIOA, p.GPIOB, p.GPIOC);
     adc.enable();
-    adc.start(r.BUFFER, p.DMA2, pwm).unwrap();
+    adc.start(r.BUFFER, p.DMA2).unwrap();
 }
 
 fn idle() -> ! {
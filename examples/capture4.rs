@@ -1,4 +1,4 @@
-//! Input capture using TIM4
+//! Input capture using all four channels of TIM4
 #![deny(unsafe_code)]
 #![deny(warnings)]
 #![feature(proc_macro)]
@@ -11,6 +11,7 @@ extern crate cortex_m_rtfm as rtfm;
 extern crate nb;
 extern crate stm32_f429_bgt6;
 
+use stm32_f429_bgt6::capture;
 use stm32_f429_bgt6::clock;
 use stm32_f429_bgt6::time::Milliseconds;
 use stm32_f429_bgt6::{Capture, Channel};
@@ -19,21 +20,23 @@ use rtfm::{app, Threshold};
 
 use stm32_f429_bgt6::stm32f40x as stm32f429x; //VERY IMPORTANT! Always do this to clarify what the base device crate really is!
 
+// One counter tick per millisecond
 const RESOLUTION: Milliseconds = Milliseconds(1);
-const CHANNELS: [Channel; 1] = [Channel::_1];
+const CHANNELS: [Channel; 4] = [Channel::_1, Channel::_2, Channel::_3, Channel::_4];
 
 app! {
     device: stm32f429x,
 
     idle: {
-        resources: [TIM2],
+        resources: [TIM4],
     },
 }
 
 fn init(p: init::Peripherals) {
     clock::set_84_mhz(&p.RCC, &p.FLASH);
-    let capture = Capture(p.TIM2);
+    let capture = Capture(p.TIM4);
 
+    // CH1 = PB6, CH2 = PB7, CH3 = PB8, CH4 = PB9
     for c in &CHANNELS {
         capture.init(RESOLUTION, *c, p.GPIOA, p.GPIOB, p.GPIOC, p.RCC);
 
@@ -42,26 +45,27 @@ fn init(p: init::Peripherals) {
 }
 
 fn idle(_t: &mut Threshold, r: idle::Resources) -> ! {
-    let mut t0: u32 = 0;
-    let capture = Capture(&*r.TIM2);
+    // Last capture of each channel, the deltas of a channel must not be
+    // computed against the captures of another one
+    let mut t0: [Option<u32>; 4] = [None; 4];
+    let capture = Capture(&*r.TIM4);
     loop {
-        for c in &CHANNELS {
+        for (i, c) in CHANNELS.iter().enumerate() {
             match capture.capture(*c) {
                 Ok(t1) => {
-                    // Calcualte delta time since last capture
-                    let dt: u32 = t1.wrapping_sub(t0);
-                    t0 = t1;
-                    // Time is in APB1 peripheral clock domain
-                    let dt_ticks = f4::frequency::apb1::Ticks(dt);
-                    // Convert it to system time in milliseconds
-                    let dt_ms: Milliseconds = f4::frequency::ahb1::Ticks(dt_ticks.into()).into();
-                    // Print it to ITM
-                    ipln!("{:?}: {:?} ms", c, dt_ms);
+                    if let Some(t0) = t0[i] {
+                        // TIM4 is a 16-bit timer, the counter wraps at 0xffff
+                        let dt_ms = t1.wrapping_sub(t0) & 0xffff;
+                        // Print it to ITM
+                        ipln!("{:?}: {} ms", c, dt_ms);
+                    }
+                    t0[i] = Some(t1);
                 }
                 Err(nb::Error::WouldBlock) => {} // Keep looping
-                Err(nb::Error::Other(f4::capture::Error::Overcapture)) => {
+                Err(nb::Error::Other(capture::Error::Overcapture)) => {
                     ipln!("{:?}: Overcapture", c);
                     capture.clear(*c);
+                    t0[i] = None;
                 }
                 Err(nb::Error::Other(e)) => {
                     ipln!("{:?}: {:?}", c, e);
@@ -1,6 +1,6 @@
 //! Analog to Digital Converter (ADC)
 //!
-//! You can use the ADC interface with these instances
+//! You can use the `Adc` interface with these instances
 //!
 //! # ADC1
 //! - IN0  = PA0
@@ -22,7 +22,7 @@
 //! - EXTI11 = PJ11/PI11/PH11/PG11/PF11/PE11/PD11/PC11/PB11/PA11
 //! - EXTI15 = PJ15/PI15/PH15/PG15/PF15/PE15/PD15/PC15/PB15/PA15
 //!
-//! # ADC2 -
+//! # ADC2
 //! - IN0  = PA0
 //! - IN1  = PA1
 //! - IN2  = PA2
@@ -61,339 +61,1459 @@
 //! - IN15 = PF5
 //! - EXTI11 = PJ11/PI11/PH11/PG11/PF11/PE11/PD11/PC11/PB11/PA11
 //! - EXTI15 = PJ15/PI15/PH15/PG15/PF15/PE15/PD15/PC15/PB15/PA15
+//!
+//! # DMA
+//!
+//! Scan conversions are moved to memory by DMA2, each ADC can use one of two
+//! streams (RM0090 10.3.3 Table 43)
+//!
+//! - ADC1: Stream 0 or Stream 4, channel 0
+//! - ADC2: Stream 2 or Stream 3, channel 1
+//! - ADC3: Stream 0 or Stream 1, channel 2
+//!
+//! # Conversion settings
+//!
+//! `init` selects 12-bit right aligned samples and the shortest sample time,
+//! 3 cycles, for every channel. Use `set_sample_time`, `set_resolution` and
+//! `set_align` to change this, and `set_prescaler` to select the ADC clock,
+//! which is shared by all three ADCs. `scan_time` returns the time a complete
+//! scan sequence takes with the current settings.
+//!
+//! # Internal channels
+//!
+//! ADC1 can also convert the internal reference voltage (IN17) and either the
+//! temperature sensor or VBAT / 4 (both on IN18 on the STM32F42x), see
+//! `Adc::enable_internal`. `Calibration` reads the factory calibration values
+//! and converts samples to millivolts and degrees Celsius. The reference
+//! voltage reading gives the actual VDDA, which compensates the conversion of
+//! all other channels.
+//!
+//! # Injected conversions
+//!
+//! Up to four channels can form an injected group with its own trigger, see
+//! `set_injected`, `set_injected_trigger` and `set_injected_offset`. An
+//! injected conversion interrupts a running regular scan, which resumes
+//! afterwards, so the group can be used next to a regular DMA scan, e.g. to
+//! sample motor currents at a fixed point of the PWM period. The results are
+//! read from the JDRx registers with `injected`.
+//!
+//! # Scan buffers
+//!
+//! The halves of a scan `CircBuffer` hold the samples of consecutive scans,
+//! interleaved in sequence order. `Adc::sequence` reads back the programmed
+//! sequence and `Sequence::view` wraps a half so that the samples of one
+//! `AdcChannel`, from every rank it's converted at, can be iterated or
+//! summarized with `Stats`:
+//!
+//! ``` ignore
+//! let sequence = adc.sequence();
+//! BUFFER.read(dma2, |half| {
+//!     let view = sequence.view(half);
+//!     let stats = view.stats(AdcChannel::_4).unwrap().millivolts(3300, Resolution::_12);
+//! })
+//! ```
+//!
+//! # Analog watchdog
+//!
+//! The analog watchdog flags, and optionally interrupts on, a conversion
+//! outside a voltage band without the CPU looking at the samples, see
+//! `set_watchdog`. It can guard a single channel or every channel of the
+//! regular and/or injected group.
+//!
+//! # Multi ADC mode
+//!
+//! ADC1 (master) can drive ADC2 or ADC2 and ADC3 (slaves) so that their
+//! conversions are simultaneous, interleaved or alternately triggered, see
+//! `set_multi_mode`. Configure the sequences of all ADCs, clear the DMA bit
+//! of the slaves with `set_dma(false)`, enable them and then call
+//! `Adc<ADC1>::start_multi`, which moves the common data register ADC_CDR
+//! into a single `CircBuffer` through ADC1's DMA stream. The packed words are
+//! split into per-ADC samples with `DualSample` and `TripleSample`.
+//!
+//! - DMA mode 1: one 16-bit sample per transfer, in ADC1, ADC2 (, ADC3) order
+//! - DMA mode 2: two 16-bit samples packed in a 32-bit word
+//! - DMA mode 3: two 8-bit samples (6 or 8-bit resolution) packed in 16 bits
+//!
+//! # One-shot conversions
+//!
+//! After `init_oneshot` single channels can be converted on demand, without
+//! DMA or a timer trigger, with `read` (non-blocking) or `convert` (blocking).
+//! The embedded-hal revision this crate depends on has no ADC traits yet, so
+//! `Adc` implements the local `OneShot` trait instead. Unlike a later
+//! embedded-hal's `adc::OneShot<ADC, Word, Pin: Channel<ADC>>`, which
+//! identifies the channel through the pin's type, it takes the channel as a
+//! value because channels are selected at run time with `AdcChannel`.
 
 use core::marker::Unsize;
+use core::ptr;
 
-use cast::u16;
-use hal::prelude::*;
+use m::Float as _0;
+use nb;
 use static_ref::Static;
 
-use dma::{self, CircBuffer, Dma2Stream0};
-use stm32f40x::{ADC1, ADC2, DMA2, TIM2, GPIOA, GPIOB, GPIOC, RCC};
-use {Channel, Pwm};
+use dma::{self, CircBuffer, Config, Direction, Dma2Stream0, Dma2Stream1, Dma2Stream2, Dma2Stream3,
+          Dma2Stream4, DmaChannel, Flags, Priority, Size, Stream, StreamNumber};
+use stm32f40x::{ADC1, ADC2, ADC3, C_ADC, DMA2, GPIOA, GPIOB, GPIOC, GPIOF, RCC};
 
-/// Input channel associated to ADC1/2
+/// Input channel of an ADC
+///
+/// See the module documentation for the pin of each channel, it's the same
+/// for ADC1 and ADC2 but ADC3 maps some of them to port F.
 #[derive(Clone, Copy, Debug)]
 pub enum AdcChannel {
-    /// ADC1_IN0 = PA0
-    /// ADC2_IN0 = PA0
+    /// ADC1/2_IN0 = PA0, ADC3_IN0 = PA0
     _0 = 0,
-    /// ADC1_IN1 = PA1
-    /// ADC2_IN1 = PA1
+    /// ADC1/2_IN1 = PA1, ADC3_IN1 = PA1
     _1 = 1,
-    /// ADC1_IN2 = PA2
-    /// ADC2_IN2 = PA2
+    /// ADC1/2_IN2 = PA2, ADC3_IN2 = PA2
     _2 = 2,
-    /// ADC1_IN3 = PA3
-    /// ADC2_IN3 = PA3
+    /// ADC1/2_IN3 = PA3, ADC3_IN3 = PA3
     _3 = 3,
-    /// ADC1_IN4 = PA4
-    /// ADC2_IN4 = PA4
+    /// ADC1/2_IN4 = PA4, ADC3_IN4 = PF6
     _4 = 4,
-    /// ADC1_IN5 = PA5
-    /// ADC2_IN5 = PA5
+    /// ADC1/2_IN5 = PA5, ADC3_IN5 = PF7
     _5 = 5,
-    /// ADC1_IN6 = PA6
-    /// ADC2_IN6 = PA6
+    /// ADC1/2_IN6 = PA6, ADC3_IN6 = PF8
     _6 = 6,
-    /// ADC1_IN7 = PA7
-    /// ADC2_IN7 = PA7
+    /// ADC1/2_IN7 = PA7, ADC3_IN7 = PF9
     _7 = 7,
-    /// ADC1_IN8 = PB0
-    /// ADC2_IN8 = PB0
+    /// ADC1/2_IN8 = PB0, ADC3_IN8 = PF10
     _8 = 8,
-    /// ADC1_IN9 = PB1
-    /// ADC2_IN9 = PB1
+    /// ADC1/2_IN9 = PB1, ADC3_IN9 = PF3
     _9 = 9,
-    /// ADC1_IN10 = PC0
-    /// ADC2_IN10 = PC0
+    /// ADC1/2_IN10 = PC0, ADC3_IN10 = PC0
     _10 = 10,
-    /// ADC1_IN11 = PC1
-    /// ADC2_IN11 = PC1
+    /// ADC1/2_IN11 = PC1, ADC3_IN11 = PC1
     _11 = 11,
-    /// ADC1_IN12 = PC2
-    /// ADC2_IN12 = PC2
+    /// ADC1/2_IN12 = PC2, ADC3_IN12 = PC2
     _12 = 12,
-    /// ADC1_IN13 = PC3
-    /// ADC2_IN13 = PC3
+    /// ADC1/2_IN13 = PC3, ADC3_IN13 = PC3
     _13 = 13,
-    /// ADC1_IN14 = PC4
-    /// ADC2_IN14 = PC4
+    /// ADC1/2_IN14 = PC4, ADC3_IN14 = PF4
     _14 = 14,
-    /// ADC1_IN15 = PC5
-    /// ADC2_IN15 = PC5
+    /// ADC1/2_IN15 = PC5, ADC3_IN15 = PF5
     _15 = 15,
+    /// ADC1_IN16, temperature sensor on the STM32F40x/41x only
+    _16 = 16,
+    /// ADC1_IN17 = VREFINT
+    _17 = 17,
+    /// ADC1_IN18 = temperature sensor or VBAT / 4
+    _18 = 18,
 }
 
-/// ADC1
-pub struct Adc1<'a>(pub &'a ADC1);
+/// Address of the VREFINT calibration value
+const VREFINT_CAL: usize = 0x1fff_7a2a;
 
-impl<'a> Adc1<'a> {
-    /// Enables the ADC input
-    pub fn enable_input(
-        &self,
-        input: AdcChannel,
-        sq: u8,
-        gpioa: &GPIOA,
-        gpiob: &GPIOB,
-        gpioc: &GPIOC,
-    ) {
-        let adc1 = self.0;
+/// Address of the temperature sensor calibration value at 30 C
+const TS_CAL1: usize = 0x1fff_7a2c;
+
+/// Address of the temperature sensor calibration value at 110 C
+const TS_CAL2: usize = 0x1fff_7a2e;
 
-        // RM0368 11.12.9
+/// VDDA during factory calibration, in millivolts
+pub const VDDA_CAL: u32 = 3_300;
+
+/// Factory calibration of the internal channels
+///
+/// All values are 12-bit samples taken with VDDA = `VDDA_CAL`, so samples
+/// passed to the methods below must be 12-bit and right aligned.
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    /// VREFINT sample
+    pub vrefint: u16,
+    /// Temperature sensor sample at 30 C
+    pub ts_cal1: u16,
+    /// Temperature sensor sample at 110 C
+    pub ts_cal2: u16,
+}
+
+impl Calibration {
+    /// Reads the calibration values from system memory
+    pub fn read() -> Self {
         unsafe {
-            match sq {
-                1 => adc1.sqr3.modify(|_, w| w.sq1().bits(input as u8)),
-                2 => adc1.sqr3.modify(|_, w| w.sq2().bits(input as u8)),
-                3 => adc1.sqr3.modify(|_, w| w.sq3().bits(input as u8)),
-                4 => adc1.sqr3.modify(|_, w| w.sq4().bits(input as u8)),
-                5 => adc1.sqr3.modify(|_, w| w.sq5().bits(input as u8)),
-                6 => adc1.sqr3.modify(|_, w| w.sq6().bits(input as u8)),
-                7 => adc1.sqr2.modify(|_, w| w.sq7().bits(input as u8)),
-                8 => adc1.sqr2.modify(|_, w| w.sq8().bits(input as u8)),
-                9 => adc1.sqr2.modify(|_, w| w.sq9().bits(input as u8)),
-                10 => adc1.sqr2.modify(|_, w| w.sq10().bits(input as u8)),
-                11 => adc1.sqr2.modify(|_, w| w.sq11().bits(input as u8)),
-                12 => adc1.sqr2.modify(|_, w| w.sq12().bits(input as u8)),
-                13 => adc1.sqr1.modify(|_, w| w.sq13().bits(input as u8)),
-                14 => adc1.sqr1.modify(|_, w| w.sq14().bits(input as u8)),
-                15 => adc1.sqr1.modify(|_, w| w.sq15().bits(input as u8)),
-                16 => adc1.sqr1.modify(|_, w| w.sq16().bits(input as u8)),
-                _ => panic!("invalid sequence register"),
+            Calibration {
+                vrefint: ptr::read_volatile(VREFINT_CAL as *const u16),
+                ts_cal1: ptr::read_volatile(TS_CAL1 as *const u16),
+                ts_cal2: ptr::read_volatile(TS_CAL2 as *const u16),
             }
         }
+    }
 
-        // Use as many conversions as maximum channel sequence number
-        let l = adc1.sqr1.read().l().bits();
-        if l < sq {
-            adc1.sqr1
-                .modify(|_, w| unsafe { w.l().bits(sq.wrapping_sub(1)) });
+    /// Returns VDDA, in millivolts, from a VREFINT sample
+    pub fn vdda(&self, vrefint: u16) -> u32 {
+        VDDA_CAL * u32::from(self.vrefint) / u32::from(vrefint.max(1))
+    }
+
+    /// Converts a sample of an external channel to millivolts, `vdda` is the
+    /// value returned by `Calibration::vdda`
+    pub fn millivolts(&self, sample: u16, vdda: u32) -> u32 {
+        u32::from(sample) * vdda / 4095
+    }
+
+    /// Converts a VBAT sample to millivolts, VBAT is divided by 4 internally
+    pub fn vbat(&self, sample: u16, vdda: u32) -> u32 {
+        4 * self.millivolts(sample, vdda)
+    }
+
+    /// Converts a temperature sensor sample to degrees Celsius
+    pub fn temperature(&self, sample: u16, vdda: u32) -> f32 {
+        // the calibration values were taken at VDDA_CAL
+        let sample = (u32::from(sample) * vdda) as f32 / VDDA_CAL as f32;
+        let cal1 = f32::from(self.ts_cal1);
+        let cal2 = f32::from(self.ts_cal2);
+
+        (110. - 30.) * (sample - cal1) / (cal2 - cal1) + 30.
+    }
+}
+
+/// Sample time of a channel (SMPx bits of ADC_SMPR1/2)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SampleTime {
+    /// 3 cycles
+    _3 = 0b000,
+    /// 15 cycles
+    _15 = 0b001,
+    /// 28 cycles
+    _28 = 0b010,
+    /// 56 cycles
+    _56 = 0b011,
+    /// 84 cycles
+    _84 = 0b100,
+    /// 112 cycles
+    _112 = 0b101,
+    /// 144 cycles
+    _144 = 0b110,
+    /// 480 cycles
+    _480 = 0b111,
+}
+
+impl SampleTime {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b111 {
+            0b000 => SampleTime::_3,
+            0b001 => SampleTime::_15,
+            0b010 => SampleTime::_28,
+            0b011 => SampleTime::_56,
+            0b100 => SampleTime::_84,
+            0b101 => SampleTime::_112,
+            0b110 => SampleTime::_144,
+            _ => SampleTime::_480,
         }
+    }
 
-        // Set pins as analog input
-        match input {
-            AdcChannel::_0 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl0().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder0().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr0().bits(0b00) });
-            }
-            AdcChannel::_1 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl1().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder1().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr1().bits(0b00) });
-            }
-            AdcChannel::_2 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl2().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder2().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr2().bits(0b00) });
-            }
-            AdcChannel::_3 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl3().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder3().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr3().bits(0b00) });
-            }
-            AdcChannel::_4 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl4().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder4().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr4().bits(0b00) });
-            }
-            AdcChannel::_5 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl5().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder5().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr5().bits(0b00) });
-            }
-            AdcChannel::_6 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl6().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder6().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr6().bits(0b00) });
-            }
-            AdcChannel::_7 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl7().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder7().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr7().bits(0b00) });
-            }
-            AdcChannel::_8 => unsafe {
-                gpiob.afrl.modify(|_, w| w.afrl0().bits(0));
-                gpiob.moder.modify(|_, w| w.moder0().bits(0b11));
-                gpiob.pupdr.modify(|_, w| w.pupdr0().bits(0b00));
-            },
-            AdcChannel::_9 => unsafe {
-                gpiob.afrl.modify(|_, w| w.afrl1().bits(0));
-                gpiob.moder.modify(|_, w| w.moder1().bits(0b11));
-                gpiob.pupdr.modify(|_, w| w.pupdr1().bits(0b00));
-            },
-            AdcChannel::_10 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl0().bits(0));
-                gpioc.moder.modify(|_, w| w.moder0().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr0().bits(0b00));
-            },
-            AdcChannel::_11 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl1().bits(0));
-                gpioc.moder.modify(|_, w| w.moder1().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr1().bits(0b00));
-            },
-            AdcChannel::_12 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl2().bits(0));
-                gpioc.moder.modify(|_, w| w.moder2().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr2().bits(0b00));
+    /// Number of ADC clock cycles
+    pub fn cycles(&self) -> u32 {
+        match *self {
+            SampleTime::_3 => 3,
+            SampleTime::_15 => 15,
+            SampleTime::_28 => 28,
+            SampleTime::_56 => 56,
+            SampleTime::_84 => 84,
+            SampleTime::_112 => 112,
+            SampleTime::_144 => 144,
+            SampleTime::_480 => 480,
+        }
+    }
+}
+
+/// Conversion resolution (RES bits of ADC_CR1)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    /// 12 bits
+    _12 = 0b00,
+    /// 10 bits
+    _10 = 0b01,
+    /// 8 bits
+    _8 = 0b10,
+    /// 6 bits
+    _6 = 0b11,
+}
+
+impl Resolution {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b00 => Resolution::_12,
+            0b01 => Resolution::_10,
+            0b10 => Resolution::_8,
+            _ => Resolution::_6,
+        }
+    }
+
+    /// Number of bits of a sample, which is also the number of ADC clock
+    /// cycles the conversion takes after sampling
+    pub fn bits(&self) -> u32 {
+        match *self {
+            Resolution::_12 => 12,
+            Resolution::_10 => 10,
+            Resolution::_8 => 8,
+            Resolution::_6 => 6,
+        }
+    }
+}
+
+/// Data alignment in ADC_DR (ALIGN bit of ADC_CR2)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Align {
+    /// The sample occupies the low bits
+    Right,
+    /// The sample occupies the high bits
+    Left,
+}
+
+/// ADC clock prescaler (ADCPRE bits of ADC_CCR)
+///
+/// The ADC clock is PCLK2 divided by this value and must not exceed 36 MHz.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Prescaler {
+    /// PCLK2 / 2
+    _2 = 0b00,
+    /// PCLK2 / 4
+    _4 = 0b01,
+    /// PCLK2 / 6
+    _6 = 0b10,
+    /// PCLK2 / 8
+    _8 = 0b11,
+}
+
+impl Prescaler {
+    /// Division ratio
+    pub fn ratio(&self) -> u32 {
+        2 * (*self as u32 + 1)
+    }
+}
+
+/// Selects the clock prescaler of all three ADCs
+pub fn set_prescaler(common: &C_ADC, prescaler: Prescaler) {
+    // RM0368 11.12.17
+    // ADCPRE[17:16]
+    common.ccr.modify(|r, w| unsafe {
+        w.bits((r.bits() & !(0b11 << 16)) | ((prescaler as u32) << 16))
+    });
+}
+
+/// Returns the clock prescaler of the ADCs
+pub fn get_prescaler(common: &C_ADC) -> Prescaler {
+    match (common.ccr.read().bits() >> 16) & 0b11 {
+        0b00 => Prescaler::_2,
+        0b01 => Prescaler::_4,
+        0b10 => Prescaler::_6,
+        _ => Prescaler::_8,
+    }
+}
+
+/// Trigger edge (EXTEN / JEXTEN bits of ADC_CR2)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Edge {
+    /// Trigger detection disabled, conversions are started in software
+    Disabled = 0b00,
+    /// Rising edge
+    Rising = 0b01,
+    /// Falling edge
+    Falling = 0b10,
+    /// Rising and falling edges
+    Both = 0b11,
+}
+
+/// Regular sequence trigger (EXTSEL bits of ADC_CR2)
+///
+/// A timer CC event requires the channel to be in output compare or PWM
+/// mode, but not its pin, so the pin can be used for something else, e.g.
+/// as an ADC input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    /// TIM1 CC1 event
+    Tim1Cc1 = 0b0000,
+    /// TIM1 CC2 event
+    Tim1Cc2 = 0b0001,
+    /// TIM1 CC3 event
+    Tim1Cc3 = 0b0010,
+    /// TIM2 CC2 event
+    Tim2Cc2 = 0b0011,
+    /// TIM2 CC3 event
+    Tim2Cc3 = 0b0100,
+    /// TIM2 CC4 event
+    Tim2Cc4 = 0b0101,
+    /// TIM2 TRGO event
+    Tim2Trgo = 0b0110,
+    /// TIM3 CC1 event
+    Tim3Cc1 = 0b0111,
+    /// TIM3 TRGO event
+    Tim3Trgo = 0b1000,
+    /// TIM4 CC4 event
+    Tim4Cc4 = 0b1001,
+    /// TIM5 CC1 event
+    Tim5Cc1 = 0b1010,
+    /// TIM5 CC2 event
+    Tim5Cc2 = 0b1011,
+    /// TIM5 CC3 event
+    Tim5Cc3 = 0b1100,
+    /// TIM8 CC1 event
+    Tim8Cc1 = 0b1101,
+    /// TIM8 TRGO event
+    Tim8Trgo = 0b1110,
+    /// EXTI line 11
+    Exti11 = 0b1111,
+}
+
+/// Injected group trigger (JEXTSEL bits of ADC_CR2)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InjectedTrigger {
+    /// TIM1 CC4 event
+    Tim1Cc4 = 0b0000,
+    /// TIM1 TRGO event
+    Tim1Trgo = 0b0001,
+    /// TIM2 CC1 event
+    Tim2Cc1 = 0b0010,
+    /// TIM2 TRGO event
+    Tim2Trgo = 0b0011,
+    /// TIM3 CC2 event
+    Tim3Cc2 = 0b0100,
+    /// TIM3 CC4 event
+    Tim3Cc4 = 0b0101,
+    /// TIM4 CC1 event
+    Tim4Cc1 = 0b0110,
+    /// TIM4 CC2 event
+    Tim4Cc2 = 0b0111,
+    /// TIM4 CC3 event
+    Tim4Cc3 = 0b1000,
+    /// TIM4 TRGO event
+    Tim4Trgo = 0b1001,
+    /// TIM5 CC4 event
+    Tim5Cc4 = 0b1010,
+    /// TIM5 TRGO event
+    Tim5Trgo = 0b1011,
+    /// TIM8 CC2 event
+    Tim8Cc2 = 0b1100,
+    /// TIM8 CC3 event
+    Tim8Cc3 = 0b1101,
+    /// TIM8 CC4 event
+    Tim8Cc4 = 0b1110,
+    /// EXTI line 15
+    Exti15 = 0b1111,
+}
+
+/// Conversions guarded by the analog watchdog
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Group {
+    /// Regular conversions
+    Regular,
+    /// Injected conversions
+    Injected,
+    /// Regular and injected conversions
+    Both,
+}
+
+/// Channels guarded by the analog watchdog
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Watchdog {
+    /// A single channel
+    Channel(AdcChannel, Group),
+    /// All channels
+    All(Group),
+}
+
+/// Converts a voltage, in millivolts, to a sample at `resolution`, right
+/// aligned
+///
+/// `vdda` is the analog supply voltage, in millivolts, e.g. the value
+/// returned by `Calibration::vdda`. Voltages above `vdda` saturate to the
+/// full scale sample, as does any voltage when `vdda` is 0.
+pub fn millivolts_to_sample(mv: u32, vdda: u32, resolution: Resolution) -> u16 {
+    let max: u32 = (1 << resolution.bits()) - 1;
+    if mv >= vdda {
+        return max as u16;
+    }
+
+    ((u64::from(mv) * u64::from(max) + u64::from(vdda / 2)) / u64::from(vdda)) as u16
+}
+
+/// Multi ADC mode (MULTI bits of ADC_CCR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MultiMode {
+    /// All ADCs independent
+    Independent = 0b00000,
+    /// Dual, combined regular simultaneous and injected simultaneous
+    DualRegularInjected = 0b00001,
+    /// Dual, combined regular simultaneous and alternate trigger
+    DualRegularAlternate = 0b00010,
+    /// Dual, injected simultaneous only
+    DualInjected = 0b00101,
+    /// Dual, regular simultaneous only
+    DualRegular = 0b00110,
+    /// Dual, interleaved only
+    DualInterleaved = 0b00111,
+    /// Dual, alternate trigger only
+    DualAlternate = 0b01001,
+    /// Triple, combined regular simultaneous and injected simultaneous
+    TripleRegularInjected = 0b10001,
+    /// Triple, combined regular simultaneous and alternate trigger
+    TripleRegularAlternate = 0b10010,
+    /// Triple, injected simultaneous only
+    TripleInjected = 0b10101,
+    /// Triple, regular simultaneous only
+    TripleRegular = 0b10110,
+    /// Triple, interleaved only
+    TripleInterleaved = 0b10111,
+    /// Triple, alternate trigger only
+    TripleAlternate = 0b11001,
+}
+
+/// Multi ADC DMA mode (DMA bits of ADC_CCR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MultiDma {
+    /// DMA disabled
+    Disabled = 0b00,
+    /// Mode 1, one 16-bit sample per request
+    Mode1 = 0b01,
+    /// Mode 2, two 16-bit samples per 32-bit request
+    Mode2 = 0b10,
+    /// Mode 3, two 8-bit samples per 16-bit request
+    Mode3 = 0b11,
+}
+
+/// Selects the multi ADC mode
+///
+/// `delay` is the delay between two sampling phases in interleaved mode, 5 -
+/// 20 ADC clock cycles.
+pub fn set_multi_mode(common: &C_ADC, mode: MultiMode, dma: MultiDma, delay: u8) {
+    assert!(delay >= 5 && delay <= 20, "invalid sampling delay");
+
+    // RM0090 13.13.16
+    // DMA[15:14], DDS[13], DELAY[11:8], MULTI[4:0]
+    // DDS: DMA requests are issued as long as data are converted
+    common.ccr.modify(|r, w| unsafe {
+        w.bits(
+            (r.bits() & !0xff1f) | ((dma as u32) << 14) | (1 << 13) |
+                (u32::from(delay - 5) << 8) | mode as u32,
+        )
+    });
+}
+
+/// Returns the multi ADC DMA mode
+pub fn get_multi_dma(common: &C_ADC) -> MultiDma {
+    match (common.ccr.read().bits() >> 14) & 0b11 {
+        0b00 => MultiDma::Disabled,
+        0b01 => MultiDma::Mode1,
+        0b10 => MultiDma::Mode2,
+        _ => MultiDma::Mode3,
+    }
+}
+
+/// Word moved by the multi ADC DMA
+pub trait MultiWord: Copy {
+    /// MSIZE / PSIZE of the DMA stream
+    fn size() -> Size;
+}
+
+impl MultiWord for u16 {
+    fn size() -> Size {
+        Size::HalfWord
+    }
+}
+
+impl MultiWord for u32 {
+    fn size() -> Size {
+        Size::Word
+    }
+}
+
+/// A pair of simultaneous samples of ADC1 and ADC2
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DualSample {
+    /// ADC1 sample
+    pub adc1: u16,
+    /// ADC2 sample
+    pub adc2: u16,
+}
+
+impl DualSample {
+    /// Splits a DMA mode 2 word
+    pub fn from_word(word: u32) -> Self {
+        DualSample {
+            adc1: word as u16,
+            adc2: (word >> 16) as u16,
+        }
+    }
+
+    /// Splits a DMA mode 3 half word
+    pub fn from_bytes(half: u16) -> Self {
+        DualSample {
+            adc1: half & 0xff,
+            adc2: half >> 8,
+        }
+    }
+
+    /// Pairs two consecutive DMA mode 1 samples
+    pub fn from_halves(halves: &[u16; 2]) -> Self {
+        DualSample {
+            adc1: halves[0],
+            adc2: halves[1],
+        }
+    }
+}
+
+/// A triple of simultaneous samples of ADC1, ADC2 and ADC3
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TripleSample {
+    /// ADC1 sample
+    pub adc1: u16,
+    /// ADC2 sample
+    pub adc2: u16,
+    /// ADC3 sample
+    pub adc3: u16,
+}
+
+impl TripleSample {
+    /// Groups three consecutive DMA mode 1 samples
+    pub fn from_halves(halves: &[u16; 3]) -> Self {
+        TripleSample {
+            adc1: halves[0],
+            adc2: halves[1],
+            adc3: halves[2],
+        }
+    }
+
+    /// Splits three consecutive DMA mode 2 words, which hold two triples as
+    /// ADC2:ADC1, ADC1:ADC3 and ADC3:ADC2 (high:low)
+    pub fn from_words(words: &[u32; 3]) -> [Self; 2] {
+        [
+            TripleSample {
+                adc1: words[0] as u16,
+                adc2: (words[0] >> 16) as u16,
+                adc3: words[1] as u16,
             },
-            AdcChannel::_13 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl3().bits(0));
-                gpioc.moder.modify(|_, w| w.moder3().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr3().bits(0b00));
+            TripleSample {
+                adc1: (words[1] >> 16) as u16,
+                adc2: words[2] as u16,
+                adc3: (words[2] >> 16) as u16,
             },
-            AdcChannel::_14 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl4().bits(0));
-                gpioc.moder.modify(|_, w| w.moder4().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr4().bits(0b00));
+        ]
+    }
+
+    /// Splits three consecutive DMA mode 3 half words, which hold two triples
+    /// as ADC2:ADC1, ADC1:ADC3 and ADC3:ADC2 (high:low)
+    pub fn from_bytes(halves: &[u16; 3]) -> [Self; 2] {
+        [
+            TripleSample {
+                adc1: halves[0] & 0xff,
+                adc2: halves[0] >> 8,
+                adc3: halves[1] & 0xff,
             },
-            AdcChannel::_15 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl5().bits(0));
-                gpioc.moder.modify(|_, w| w.moder5().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr5().bits(0b00));
+            TripleSample {
+                adc1: halves[1] >> 8,
+                adc2: halves[2] & 0xff,
+                adc3: halves[2] >> 8,
             },
+        ]
+    }
+}
+
+/// Converts a right aligned sample at `resolution` to millivolts, `vdda`
+/// being the analog supply voltage in millivolts
+pub fn sample_to_millivolts(sample: u16, vdda: u32, resolution: Resolution) -> u32 {
+    u32::from(sample) * vdda / ((1 << resolution.bits()) - 1)
+}
+
+/// Regular scan sequence read back from an ADC
+#[derive(Clone, Copy, Debug)]
+pub struct Sequence {
+    channels: [u8; 16],
+    len: usize,
+}
+
+impl Sequence {
+    /// Number of conversions in a scan
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the channel converted at `index` (0 based) of a scan
+    pub fn channel(&self, index: usize) -> AdcChannel {
+        assert!(index < self.len, "index out of sequence");
+
+        match self.channels[index] {
+            0 => AdcChannel::_0,
+            1 => AdcChannel::_1,
+            2 => AdcChannel::_2,
+            3 => AdcChannel::_3,
+            4 => AdcChannel::_4,
+            5 => AdcChannel::_5,
+            6 => AdcChannel::_6,
+            7 => AdcChannel::_7,
+            8 => AdcChannel::_8,
+            9 => AdcChannel::_9,
+            10 => AdcChannel::_10,
+            11 => AdcChannel::_11,
+            12 => AdcChannel::_12,
+            13 => AdcChannel::_13,
+            14 => AdcChannel::_14,
+            15 => AdcChannel::_15,
+            16 => AdcChannel::_16,
+            17 => AdcChannel::_17,
+            _ => AdcChannel::_18,
         }
     }
 
-    /// Initializes the ADC
-    ///
-    /// NOTE `Pwm<TIM2>.init` must be called before this method because both
-    /// methods configure the PA1 pin (one as input and the other as output :-/)
-    pub fn init(&self, dma2: &DMA2, rcc: &RCC) {
-        let adc1 = self.0;
-
-        // enable ADC1, DMA1, GPIOA, TIM2
-        rcc.ahb1enr.modify(|_, w| w.gpioaen().set_bit());
-        rcc.ahb1enr.modify(|_, w| w.gpioben().set_bit());
-        rcc.ahb1enr.modify(|_, w| w.gpiocen().set_bit());
-        rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
-        rcc.apb1enr.modify(|_, w| w.tim2en().set_bit());
-        rcc.apb2enr.modify(|_, w| w.adc1en().set_bit());
-
-        // RM0368 11.12.5
-        // Sample time: 55.5 + 12.5 = 68 cycles
-        adc1.smpr2.modify(|_, w| unsafe { w.smpx_x().bits(0) });
-
-        // ADC1
-        // chsel: Channel 0 (RM0368 9.3.3 Table 27)
-        // pl: Medium priority
-        // msize: Memory size = 16 bits
-        // psize: Peripheral size = 16 bits
-        // minc: Memory increment mode enabled
-        // pinc: Peripheral increment mode disabled
-        // circ: Circular mode enabled
-        // dir: Transfer from peripheral to memory
-        // htie: Half transfer interrupt enabled
-        // tceie: Transfer complete interrupt enabled
-        // en: Disabled
-        dma2.s0cr.write(|w| unsafe {
-            w.chsel()
-                .bits(0)
-                .pl()
-                .bits(0b01)
-                .msize()
-                .bits(0b01)
-                .psize()
-                .bits(0b01)
-                .minc()
-                .set_bit()
-                .circ()
-                .set_bit()
-                .pinc()
-                .clear_bit()
-                .dir()
-                .bits(0)
-                .htie()
-                .set_bit()
-                .tcie()
-                .set_bit()
-                .en()
-                .clear_bit()
-        });
-        // RM0368 11.12.3
-        // exten: Conversion on external trigger rising edge
-        // extsel: Timer 2 CC2 event
-        // align: Right alignment
-        // dma: DMA mode enabled
-        // dds: DMA requests are issued as long as data are converted and DMA=1
-        // cont: Single conversion mode
-        // adon: Disable ADC conversion
-        adc1.cr2.write(|w| unsafe {
-            w.exten()
-                .bits(0b01)
-                .extsel()
-                .bits(0b011) // T2C2
-                .align()
-                .clear_bit()
-                .dma()
-                .set_bit()
-                .dds()
-                .set_bit()
-                .cont()
-                .clear_bit()
-                .adon()
-                .clear_bit()
-        });
-        // RM0368 11.3.8 and 11.12.2
-        // scan: Scan mode enabled
-        adc1.cr1.write(|w| w.scan().set_bit());
+    /// Returns the index of the first conversion of `input` in a scan
+    pub fn position(&self, input: AdcChannel) -> Option<usize> {
+        self.channels[..self.len]
+            .iter()
+            .position(|ch| *ch == input as u8)
     }
 
-    /// Disables the ADC
-    pub fn disable(&self) {
-        self.0.cr2.modify(|_, w| w.adon().clear_bit());
+    /// Wraps a scan buffer half, which must hold whole scans
+    pub fn view<'a>(&'a self, samples: &'a [u16]) -> ScanView<'a> {
+        assert_eq!(samples.len() % self.len, 0, "buffer doesn't hold whole scans");
+
+        ScanView {
+            sequence: self,
+            samples: samples,
+        }
     }
+}
 
-    /// Enables the ADC
-    pub fn enable(&self) {
-        self.0.cr2.modify(|_, w| w.adon().set_bit());
+/// Per-channel access to a scan buffer half
+#[derive(Clone, Copy)]
+pub struct ScanView<'a> {
+    sequence: &'a Sequence,
+    samples: &'a [u16],
+}
+
+impl<'a> ScanView<'a> {
+    /// Number of complete scans
+    pub fn scans(&self) -> usize {
+        self.samples.len() / self.sequence.len
     }
 
-    /// Starts an analog to digital conversion that will be periodically
-    /// triggered by the channel 2 of TIM2
-    ///
-    /// The conversions will be stored in the circular `buffer`
-    pub fn start<B>(
-        &self,
-        buffer: &Static<CircBuffer<B, Dma2Stream0>>,
-        dma2: &DMA2,
-        pwm: Pwm<TIM2>,
-    ) -> Result<(), dma::Error>
-    where
-        B: Unsize<[u16]>,
-    {
-        let adc1 = self.0;
+    /// Samples of `input` in conversion order, including those of every
+    /// rank `input` is repeated at, `None` if it's not part of the sequence
+    pub fn channel(&self, input: AdcChannel) -> Option<ChannelSamples<'a>> {
+        let sequence = self.sequence;
+
+        sequence.position(input).map(|index| {
+            ChannelSamples {
+                channels: &sequence.channels[..sequence.len],
+                input: input as u8,
+                samples: self.samples,
+                next: index,
+            }
+        })
+    }
+
+    /// Statistics of the samples of `input`
+    pub fn stats(&self, input: AdcChannel) -> Option<Stats> {
+        self.channel(input).map(|samples| samples.stats())
+    }
+}
+
+/// Iterator over the samples of one channel of a scan buffer
+#[derive(Clone)]
+pub struct ChannelSamples<'a> {
+    /// Channel of each rank of the sequence
+    channels: &'a [u8],
+    input: u8,
+    samples: &'a [u16],
+    next: usize,
+}
+
+impl<'a> Iterator for ChannelSamples<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        while self.next < self.samples.len() {
+            let index = self.next;
+            self.next += 1;
+
+            if self.channels[index % self.channels.len()] == self.input {
+                return Some(self.samples[index]);
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> ChannelSamples<'a> {
+    /// Computes the statistics of the remaining samples
+    pub fn stats(self) -> Stats {
+        let mut min = u16::max_value();
+        let mut max = 0;
+        let mut sum = 0u32;
+        let mut sum_sq = 0u64;
+        let mut count = 0u32;
+
+        for sample in self {
+            min = min.min(sample);
+            max = max.max(sample);
+            sum += u32::from(sample);
+            sum_sq += u64::from(sample) * u64::from(sample);
+            count += 1;
+        }
+
+        if count == 0 {
+            return Stats {
+                min: 0.,
+                max: 0.,
+                mean: 0.,
+                rms: 0.,
+            };
+        }
 
-        if dma2.s0cr.read().en().bit_is_set() {
-            return Err(dma::Error::InUse);
+        Stats {
+            min: f32::from(min),
+            max: f32::from(max),
+            mean: sum as f32 / count as f32,
+            rms: (sum_sq as f32 / count as f32).sqrt(),
         }
+    }
+}
 
-        pwm.disable(Channel::_2);
-        pwm.set_duty(Channel::_2, 1);
+/// Statistics of the samples of a channel
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+    /// Smallest sample
+    pub min: f32,
+    /// Largest sample
+    pub max: f32,
+    /// Mean
+    pub mean: f32,
+    /// Root mean square
+    pub rms: f32,
+}
 
-        let buffer: &[u16] = &buffer.lock()[0];
+impl Stats {
+    /// Converts the statistics from sample units to millivolts
+    pub fn millivolts(self, vdda: u32, resolution: Resolution) -> Stats {
+        let scale = vdda as f32 / ((1 << resolution.bits()) - 1) as f32;
 
-        dma2.s0ndtr
-            .write(|w| unsafe { w.ndt().bits(u16(buffer.len() * 2).unwrap()) });
+        Stats {
+            min: self.min * scale,
+            max: self.max * scale,
+            mean: self.mean * scale,
+            rms: self.rms * scale,
+        }
+    }
+}
+
+/// Single conversion of an analog input
+///
+/// `Pin` is a value that selects the input, e.g. `AdcChannel`; see the module
+/// documentation for how this differs from embedded-hal.
+pub trait OneShot<Word, Pin> {
+    /// Conversion error
+    type Error;
 
-        dma2.s0par
-            .write(|w| unsafe { w.bits(&adc1.dr as *const _ as u32) });
+    /// Starts a conversion of `pin` if none is in progress and returns its
+    /// result once it's done
+    fn read(&mut self, pin: &mut Pin) -> nb::Result<Word, Self::Error>;
+}
 
-        dma2.s0m0ar
-            .write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+/// Analog to Digital Converter
+pub struct Adc<'a, T>(pub &'a T)
+where
+    T: 'a;
+
+/// Sets a pin as analog input
+macro_rules! analog {
+    ($gpio:ident, $moder:ident, $pupdr:ident) => {{
+        $gpio.moder.modify(|_, w| unsafe { w.$moder().bits(0b11) });
+        $gpio.pupdr.modify(|_, w| unsafe { w.$pupdr().bits(0b00) });
+    }}
+}
 
-        dma2.s0cr.modify(|_, w| w.en().set_bit());
-        pwm.enable(Channel::_2);
+/// DMA2 stream that can move the conversions of `ADC`, see the module
+/// documentation
+pub trait AdcStream<ADC> {
+    /// Returns the request channel (CHSEL) of the ADC on the stream
+    fn request() -> DmaChannel;
+}
 
-        Ok(())
+/// Circular peripheral to memory transfer of `size` words for the request
+/// `channel`, with half and full transfer interrupts
+fn dma_config(channel: DmaChannel, size: Size) -> Config {
+    Config {
+        channel: channel,
+        priority: Priority::Medium,
+        direction: Direction::PeripheralToMemory,
+        msize: size,
+        psize: size,
+        minc: true,
+        pinc: false,
+        circular: true,
+        interrupts: Flags {
+            half_transfer: true,
+            transfer_complete: true,
+            ..Flags::default()
+        },
     }
 }
 
-/// ADC2  (Have now changed all ADC1 to ADC2, need to test! Does it need it's own DMA? It's own tim trigger?)
-pub struct Adc2<'a>(pub &'a ADC2);
+macro_rules! impl_Adc {
+    ($ADC:ident, $adcen:ident, $chsel:ident, [$SA:ident, $SB:ident], [$($gpioen:ident),+]) => {
+        impl AdcStream<$ADC> for $SA {
+            fn request() -> DmaChannel {
+                DmaChannel::$chsel
+            }
+        }
+
+        impl AdcStream<$ADC> for $SB {
+            fn request() -> DmaChannel {
+                DmaChannel::$chsel
+            }
+        }
+
+        impl<'a> Adc<'a, $ADC> {
+            /// Puts `input` at position `sq` (1 - 16) of the regular sequence
+            fn set_sequence(&self, input: AdcChannel, sq: u8) {
+                let adc = self.0;
+
+                // RM0368 11.12.9
+                unsafe {
+                    match sq {
+                        1 => adc.sqr3.modify(|_, w| w.sq1().bits(input as u8)),
+                        2 => adc.sqr3.modify(|_, w| w.sq2().bits(input as u8)),
+                        3 => adc.sqr3.modify(|_, w| w.sq3().bits(input as u8)),
+                        4 => adc.sqr3.modify(|_, w| w.sq4().bits(input as u8)),
+                        5 => adc.sqr3.modify(|_, w| w.sq5().bits(input as u8)),
+                        6 => adc.sqr3.modify(|_, w| w.sq6().bits(input as u8)),
+                        7 => adc.sqr2.modify(|_, w| w.sq7().bits(input as u8)),
+                        8 => adc.sqr2.modify(|_, w| w.sq8().bits(input as u8)),
+                        9 => adc.sqr2.modify(|_, w| w.sq9().bits(input as u8)),
+                        10 => adc.sqr2.modify(|_, w| w.sq10().bits(input as u8)),
+                        11 => adc.sqr2.modify(|_, w| w.sq11().bits(input as u8)),
+                        12 => adc.sqr2.modify(|_, w| w.sq12().bits(input as u8)),
+                        13 => adc.sqr1.modify(|_, w| w.sq13().bits(input as u8)),
+                        14 => adc.sqr1.modify(|_, w| w.sq14().bits(input as u8)),
+                        15 => adc.sqr1.modify(|_, w| w.sq15().bits(input as u8)),
+                        16 => adc.sqr1.modify(|_, w| w.sq16().bits(input as u8)),
+                        _ => panic!("invalid sequence register"),
+                    }
+                }
+
+                // Use as many conversions as maximum channel sequence number
+                let l = adc.sqr1.read().l().bits();
+                if l < sq {
+                    adc.sqr1
+                        .modify(|_, w| unsafe { w.l().bits(sq.wrapping_sub(1)) });
+                }
+            }
+
+            /// Initializes the ADC for scan conversions started by `trigger`
+            ///
+            /// The timer or EXTI line that provides the trigger must be set up
+            /// separately. `Edge::Disabled` ignores `trigger`, conversions are
+            /// then started in software.
+            pub fn init(&self, trigger: Trigger, edge: Edge, rcc: &RCC) {
+                let adc = self.0;
+
+                // enable the ADC, DMA2 and its GPIO ports
+                $(rcc.ahb1enr.modify(|_, w| w.$gpioen().set_bit());)+
+                rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+                rcc.apb2enr.modify(|_, w| w.$adcen().set_bit());
+
+                // RM0368 11.12.4 and 11.12.5
+                // Sample time: 3 cycles for all channels
+                adc.smpr1.write(|w| unsafe { w.bits(0) });
+                adc.smpr2.modify(|_, w| unsafe { w.smpx_x().bits(0) });
+
+                // RM0368 11.12.3
+                // exten: see `edge`
+                // extsel: see `trigger`
+                // align: Right alignment
+                // dma: DMA mode enabled
+                // dds: DMA requests are issued as long as data are converted and DMA=1
+                // cont: Single conversion mode
+                // adon: Disable ADC conversion
+                adc.cr2.write(|w| unsafe {
+                    w.exten()
+                        .bits(edge as u8)
+                        .extsel()
+                        .bits(trigger as u8)
+                        .align()
+                        .clear_bit()
+                        .dma()
+                        .set_bit()
+                        .dds()
+                        .set_bit()
+                        .cont()
+                        .clear_bit()
+                        .adon()
+                        .clear_bit()
+                });
+                // RM0368 11.3.8 and 11.12.2
+                // scan: Scan mode enabled
+                adc.cr1.write(|w| w.scan().set_bit());
+            }
+
+            /// Selects the trigger of the regular sequence, `Edge::Disabled`
+            /// leaves the sequence to software starts
+            pub fn set_trigger(&self, trigger: Trigger, edge: Edge) {
+                // RM0368 11.12.3
+                // EXTEN[29:28], EXTSEL[27:24]
+                self.0.cr2.modify(|r, w| unsafe {
+                    w.bits(
+                        (r.bits() & !(0b11_1111 << 24)) | ((edge as u32) << 28) |
+                            ((trigger as u32) << 24),
+                    )
+                });
+            }
+
+            /// Starts a conversion of the regular sequence in software
+            pub fn start_regular(&self) {
+                self.0.cr2.modify(|_, w| w.swstart().set_bit());
+            }
+
+            /// Disables the ADC
+            pub fn disable(&self) {
+                self.0.cr2.modify(|_, w| w.adon().clear_bit());
+            }
+
+            /// Enables the ADC
+            pub fn enable(&self) {
+                self.0.cr2.modify(|_, w| w.adon().set_bit());
+            }
+
+            /// Sets the sample time of `input`
+            pub fn set_sample_time(&self, input: AdcChannel, time: SampleTime) {
+                let adc = self.0;
+                let n = input as u32;
+
+                // RM0368 11.12.4 and 11.12.5
+                // SMPx[2:0] at 3 * x in SMPR2 for channels 0 - 9 and at
+                // 3 * (x - 10) in SMPR1 for channels 10 - 18
+                if n < 10 {
+                    let shift = 3 * n;
+                    adc.smpr2.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !(0b111 << shift)) | ((time as u32) << shift))
+                    });
+                } else {
+                    let shift = 3 * (n - 10);
+                    adc.smpr1.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !(0b111 << shift)) | ((time as u32) << shift))
+                    });
+                }
+            }
+
+            /// Returns the sample time of `input`
+            pub fn get_sample_time(&self, input: AdcChannel) -> SampleTime {
+                let adc = self.0;
+                let n = input as u32;
+
+                if n < 10 {
+                    SampleTime::from_bits(adc.smpr2.read().bits() >> (3 * n))
+                } else {
+                    SampleTime::from_bits(adc.smpr1.read().bits() >> (3 * (n - 10)))
+                }
+            }
+
+            /// Sets the resolution, only while the ADC is disabled
+            pub fn set_resolution(&self, resolution: Resolution) {
+                // RM0368 11.12.2
+                // RES[25:24]
+                self.0.cr1.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0b11 << 24)) | ((resolution as u32) << 24))
+                });
+            }
+
+            /// Returns the resolution
+            pub fn get_resolution(&self) -> Resolution {
+                Resolution::from_bits(self.0.cr1.read().bits() >> 24)
+            }
+
+            /// Configures and enables the analog watchdog
+            ///
+            /// The watchdog flag is raised when a guarded conversion is below
+            /// `low` or above `high`, both in millivolts, `vdda` being the
+            /// analog supply voltage in millivolts. The thresholds are
+            /// converted using the current resolution, so call this again
+            /// after `set_resolution`.
+            pub fn set_watchdog(&self, watchdog: Watchdog, low: u32, high: u32, vdda: u32) {
+                let adc = self.0;
+
+                assert!(low <= high, "invalid watchdog thresholds");
+
+                // RM0368 11.12.7 and 11.12.8
+                // The thresholds are compared with the 12-bit value, lower
+                // resolutions leave its low bits at zero
+                let resolution = self.get_resolution();
+                let shift = 12 - resolution.bits();
+                let low = u32::from(millivolts_to_sample(low, vdda, resolution)) << shift;
+                let high = u32::from(millivolts_to_sample(high, vdda, resolution)) << shift;
+                adc.ltr.write(|w| unsafe { w.bits(low) });
+                adc.htr.write(|w| unsafe { w.bits(high) });
+
+                let (channel, single, group) = match watchdog {
+                    Watchdog::Channel(input, group) => (input as u32, 1, group),
+                    Watchdog::All(group) => (0, 0, group),
+                };
+                let (regular, injected) = match group {
+                    Group::Regular => (1, 0),
+                    Group::Injected => (0, 1),
+                    Group::Both => (1, 1),
+                };
+
+                // RM0368 11.12.2
+                // AWDEN[23], JAWDEN[22], AWDSGL[9], AWDCH[4:0]
+                adc.cr1.modify(|r, w| unsafe {
+                    w.bits(
+                        (r.bits() & !((1 << 23) | (1 << 22) | (1 << 9) | 0b1_1111)) |
+                            (regular << 23) | (injected << 22) | (single << 9) | channel,
+                    )
+                });
+            }
+
+            /// Disables the analog watchdog
+            pub fn disable_watchdog(&self) {
+                self.0.cr1.modify(|r, w| unsafe {
+                    w.bits(r.bits() & !((1 << 23) | (1 << 22)))
+                });
+            }
+
+            /// Enables or disables the analog watchdog interrupt (AWDIE)
+            pub fn listen_watchdog(&self, enable: bool) {
+                self.0.cr1.modify(|_, w| if enable {
+                    w.awdie().set_bit()
+                } else {
+                    w.awdie().clear_bit()
+                });
+            }
+
+            /// Returns `true` if a guarded conversion left the band, clearing
+            /// the flag
+            pub fn watchdog_tripped(&self) -> bool {
+                let adc = self.0;
+
+                if adc.sr.read().awd().bit_is_set() {
+                    // AWD[0] is rc_w0, writing 1 leaves the other flags alone
+                    adc.sr.write(|w| unsafe { w.bits(!(1 << 0)) });
+                    true
+                } else {
+                    false
+                }
+            }
+
+            /// Enables or disables the DMA requests of this ADC, slave ADCs
+            /// must have them disabled in multi ADC mode
+            pub fn set_dma(&self, enable: bool) {
+                self.0.cr2.modify(|_, w| if enable {
+                    w.dma().set_bit()
+                } else {
+                    w.dma().clear_bit()
+                });
+            }
+
+            /// Sets the data alignment
+            pub fn set_align(&self, align: Align) {
+                self.0.cr2.modify(|_, w| match align {
+                    Align::Right => w.align().clear_bit(),
+                    Align::Left => w.align().set_bit(),
+                });
+            }
+
+            /// Returns the channel at position `sq` (1 - 16) of the regular
+            /// sequence
+            fn get_sequence(&self, sq: u8) -> u32 {
+                let adc = self.0;
+
+                // RM0368 11.12.9 - 11.12.11
+                // six 5-bit SQx fields per register, starting with SQR3
+                let i = u32::from(sq - 1);
+                let bits = match i / 6 {
+                    0 => adc.sqr3.read().bits(),
+                    1 => adc.sqr2.read().bits(),
+                    _ => adc.sqr1.read().bits(),
+                };
+                (bits >> (5 * (i % 6))) & 0b1_1111
+            }
+
+            /// Reads back the regular scan sequence
+            pub fn sequence(&self) -> Sequence {
+                let len = self.0.sqr1.read().l().bits() + 1;
+
+                let mut channels = [0; 16];
+                for sq in 1..len + 1 {
+                    channels[usize::from(sq - 1)] = self.get_sequence(sq) as u8;
+                }
+
+                Sequence {
+                    channels: channels,
+                    len: usize::from(len),
+                }
+            }
+
+            /// Returns the time a complete regular scan sequence takes with
+            /// the current sample times, resolution and ADC clock prescaler
+            pub fn scan_time(&self, common: &C_ADC) -> ::apb2::Ticks {
+                let adc = self.0;
+                let len = adc.sqr1.read().l().bits() + 1;
+                let conversion = self.get_resolution().bits();
+
+                let mut cycles = 0;
+                for sq in 1..len + 1 {
+                    let n = self.get_sequence(sq);
+                    let sample = if n < 10 {
+                        SampleTime::from_bits(adc.smpr2.read().bits() >> (3 * n))
+                    } else {
+                        SampleTime::from_bits(adc.smpr1.read().bits() >> (3 * (n - 10)))
+                    };
+                    cycles += sample.cycles() + conversion;
+                }
+
+                ::apb2::Ticks(cycles * get_prescaler(common).ratio())
+            }
+
+            /// Programs the injected group, 1 - 4 channels converted in this
+            /// order
+            pub fn set_injected(&self, inputs: &[AdcChannel]) {
+                let n = inputs.len();
+                assert!(n >= 1 && n <= 4, "invalid injected group length");
+
+                // RM0368 11.12.12
+                // JL[21:20] = n - 1, JSQ1 - JSQ4 5 bits each starting at bit 0.
+                // A group shorter than 4 is converted from JSQ(4 - JL), so the
+                // channels go into the last n fields.
+                let mut bits = ((n as u32) - 1) << 20;
+                for (i, input) in inputs.iter().enumerate() {
+                    bits |= (*input as u32) << (5 * (4 - n + i));
+                }
+                self.0.jsqr.write(|w| unsafe { w.bits(bits) });
+            }
+
+            /// Sets the offset subtracted from the `rank`-th (1 - 4) result of
+            /// the injected group
+            pub fn set_injected_offset(&self, rank: u8, offset: u16) {
+                let adc = self.0;
+
+                assert!(offset <= 0xfff, "invalid injected offset");
+
+                // RM0368 11.12.6
+                let offset = u32::from(offset);
+                match rank {
+                    1 => adc.jofr1.write(|w| unsafe { w.bits(offset) }),
+                    2 => adc.jofr2.write(|w| unsafe { w.bits(offset) }),
+                    3 => adc.jofr3.write(|w| unsafe { w.bits(offset) }),
+                    4 => adc.jofr4.write(|w| unsafe { w.bits(offset) }),
+                    _ => panic!("invalid injected rank"),
+                }
+            }
+
+            /// Selects the trigger of the injected group, `Edge::Disabled`
+            /// leaves the group to `start_injected`
+            pub fn set_injected_trigger(&self, trigger: InjectedTrigger, edge: Edge) {
+                // RM0368 11.12.3
+                // JEXTEN[21:20], JEXTSEL[19:16]
+                self.0.cr2.modify(|r, w| unsafe {
+                    w.bits(
+                        (r.bits() & !(0b11_1111 << 16)) | ((edge as u32) << 20) |
+                            ((trigger as u32) << 16),
+                    )
+                });
+            }
+
+            /// Starts a conversion of the injected group in software
+            pub fn start_injected(&self) {
+                let adc = self.0;
+
+                // JEOC[2] is rc_w0, writing 1 leaves the regular group's flags
+                // alone
+                adc.sr.write(|w| unsafe { w.bits(!(1 << 2)) });
+                // JSWSTART[22]
+                adc.cr2.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 22)) });
+            }
+
+            /// Enables or disables the interrupt raised at the end of the
+            /// injected group (JEOCIE)
+            pub fn listen_injected(&self, enable: bool) {
+                self.0.cr1.modify(|_, w| if enable {
+                    w.jeocie().set_bit()
+                } else {
+                    w.jeocie().clear_bit()
+                });
+            }
+
+            /// Returns the results of the injected group once it has been
+            /// converted, with the offsets subtracted
+            ///
+            /// Only the first n values are meaningful, n being the length of
+            /// the group. Clears the end of conversion flag.
+            pub fn injected(&self) -> nb::Result<[i16; 4], !> {
+                let adc = self.0;
 
-impl<'a> Adc2<'a> {
-    /// Enables the ADC input
+                if adc.sr.read().jeoc().bit_is_clear() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                // JEOC[2]
+                adc.sr.write(|w| unsafe { w.bits(!(1 << 2)) });
+
+                // RM0368 11.12.13
+                // right aligned results are sign extended when an offset
+                // makes them negative
+                Ok([
+                    adc.jdr1.read().bits() as u16 as i16,
+                    adc.jdr2.read().bits() as u16 as i16,
+                    adc.jdr3.read().bits() as u16 as i16,
+                    adc.jdr4.read().bits() as u16 as i16,
+                ])
+            }
+
+            /// Initializes the ADC for software triggered single conversions
+            ///
+            /// The pins still have to be set as analog inputs with
+            /// `enable_pin`. Every one-shot conversion rewrites the first
+            /// position of the scan sequence.
+            pub fn init_oneshot(&self, rcc: &RCC) {
+                let adc = self.0;
+
+                $(rcc.ahb1enr.modify(|_, w| w.$gpioen().set_bit());)+
+                rcc.apb2enr.modify(|_, w| w.$adcen().set_bit());
+
+                // RM0368 11.12.4 and 11.12.5
+                // Sample time: 3 cycles for all channels
+                adc.smpr1.write(|w| unsafe { w.bits(0) });
+                adc.smpr2.modify(|_, w| unsafe { w.smpx_x().bits(0) });
+
+                // RM0368 11.12.3
+                // exten: Trigger detection disabled
+                // align: Right alignment
+                // dma: DMA mode disabled
+                // eocs: EOC is set at the end of each regular conversion
+                // cont: Single conversion mode
+                // adon: Enable ADC
+                adc.cr2.write(|w| unsafe {
+                    w.exten()
+                        .bits(0b00)
+                        .align()
+                        .clear_bit()
+                        .dma()
+                        .clear_bit()
+                        .eocs()
+                        .set_bit()
+                        .cont()
+                        .clear_bit()
+                        .adon()
+                        .set_bit()
+                });
+                // scan: Scan mode disabled
+                adc.cr1.write(|w| w.scan().clear_bit());
+                adc.sqr1.modify(|_, w| unsafe { w.l().bits(0) });
+
+                // RM0368 11.12.1
+                // Clear OVR[5], STRT[4] and EOC[1] left over from a previous
+                // scan, otherwise `read_channel` would return a stale sample or
+                // wait for a conversion that never ends. The bits are rc_w0,
+                // writing 1 leaves the other flags alone.
+                adc.sr.write(|w| unsafe { w.bits(!0b11_0010) });
+            }
+
+            /// Starts a conversion of `input` if none is in progress, returns
+            /// the sample once the conversion of `input` is done
+            ///
+            /// If the pending conversion is of another channel, whose read was
+            /// abandoned, its sample is discarded once it's done and a
+            /// conversion of `input` is started instead.
+            pub fn read_channel(&self, input: AdcChannel) -> nb::Result<u16, !> {
+                let adc = self.0;
+                let sr = adc.sr.read();
+
+                if sr.strt().bit_is_set() {
+                    if sr.eoc().bit_is_clear() {
+                        // a single conversion only takes a few ADC cycles
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    // reading DR clears EOC
+                    let sample = adc.dr.read().data().bits();
+                    // RM0368 11.12.1
+                    // STRT[4] is rc_w0, writing 1 leaves the other flags alone
+                    adc.sr.write(|w| unsafe { w.bits(!(1 << 4)) });
+
+                    if adc.sqr3.read().sq1().bits() == input as u8 {
+                        return Ok(sample);
+                    }
+                }
+
+                adc.sqr3.modify(|_, w| unsafe { w.sq1().bits(input as u8) });
+                // EOC[1]
+                adc.sr.write(|w| unsafe { w.bits(!(1 << 1)) });
+                adc.cr2.modify(|_, w| w.swstart().set_bit());
+                Err(nb::Error::WouldBlock)
+            }
+
+            /// Converts `input` and waits for the result
+            pub fn convert(&self, input: AdcChannel) -> u16 {
+                loop {
+                    match self.read_channel(input) {
+                        Ok(sample) => return sample,
+                        Err(nb::Error::WouldBlock) => {}
+                        Err(nb::Error::Other(e)) => e,
+                    }
+                }
+            }
+
+            /// Starts moving the conversions of the regular sequence, started
+            /// by the trigger selected in `init` or `set_trigger`, to memory
+            ///
+            /// The conversions will be stored in the circular `buffer`, which
+            /// must use one of the two DMA2 streams of this ADC (see the module
+            /// documentation).
+            pub fn start<B, N>(
+                &self,
+                buffer: &Static<CircBuffer<B, Stream<DMA2, N>>>,
+                dma2: &DMA2,
+            ) -> Result<(), dma::Error>
+            where
+                B: Unsize<[u16]>,
+                N: StreamNumber,
+                Stream<DMA2, N>: AdcStream<$ADC>,
+            {
+                let config = dma_config(
+                    <Stream<DMA2, N> as AdcStream<$ADC>>::request(),
+                    Size::HalfWord,
+                );
+                buffer.start::<u16>(dma2, &config, &self.0.dr as *const _ as u32)
+            }
+        }
+
+        impl<'a> OneShot<u16, AdcChannel> for Adc<'a, $ADC> {
+            type Error = !;
+
+            fn read(&mut self, pin: &mut AdcChannel) -> nb::Result<u16, !> {
+                self.read_channel(*pin)
+            }
+        }
+    }
+}
+
+impl_Adc!(ADC1, adc1en, _0, [Dma2Stream0, Dma2Stream4], [gpioaen, gpioben, gpiocen]);
+impl_Adc!(ADC2, adc2en, _1, [Dma2Stream2, Dma2Stream3], [gpioaen, gpioben, gpiocen]);
+impl_Adc!(ADC3, adc3en, _2, [Dma2Stream0, Dma2Stream1], [gpioaen, gpiocen, gpiofen]);
+
+/// Sets the pin of `input` as analog input, ADC1 and ADC2 pin map
+fn set_analog_abc(input: AdcChannel, gpioa: &GPIOA, gpiob: &GPIOB, gpioc: &GPIOC) {
+    match input {
+        AdcChannel::_0 => analog!(gpioa, moder0, pupdr0),
+        AdcChannel::_1 => analog!(gpioa, moder1, pupdr1),
+        AdcChannel::_2 => analog!(gpioa, moder2, pupdr2),
+        AdcChannel::_3 => analog!(gpioa, moder3, pupdr3),
+        AdcChannel::_4 => analog!(gpioa, moder4, pupdr4),
+        AdcChannel::_5 => analog!(gpioa, moder5, pupdr5),
+        AdcChannel::_6 => analog!(gpioa, moder6, pupdr6),
+        AdcChannel::_7 => analog!(gpioa, moder7, pupdr7),
+        AdcChannel::_8 => analog!(gpiob, moder0, pupdr0),
+        AdcChannel::_9 => analog!(gpiob, moder1, pupdr1),
+        AdcChannel::_10 => analog!(gpioc, moder0, pupdr0),
+        AdcChannel::_11 => analog!(gpioc, moder1, pupdr1),
+        AdcChannel::_12 => analog!(gpioc, moder2, pupdr2),
+        AdcChannel::_13 => analog!(gpioc, moder3, pupdr3),
+        AdcChannel::_14 => analog!(gpioc, moder4, pupdr4),
+        AdcChannel::_15 => analog!(gpioc, moder5, pupdr5),
+        AdcChannel::_16 | AdcChannel::_17 | AdcChannel::_18 => {}
+    }
+}
+
+impl<'a> Adc<'a, ADC1> {
+    /// Enables the ADC input and puts it at position `sq` (1 - 16) of the
+    /// scan sequence
     pub fn enable_input(
         &self,
         input: AdcChannel,
@@ -402,253 +1522,163 @@ impl<'a> Adc2<'a> {
         gpiob: &GPIOB,
         gpioc: &GPIOC,
     ) {
-        let adc2 = self.0;
-
-        // RM0368 11.12.9
-        unsafe {
-            match sq {
-                1 => adc2.sqr3.modify(|_, w| w.sq1().bits(input as u8)),
-                2 => adc2.sqr3.modify(|_, w| w.sq2().bits(input as u8)),
-                3 => adc2.sqr3.modify(|_, w| w.sq3().bits(input as u8)),
-                4 => adc2.sqr3.modify(|_, w| w.sq4().bits(input as u8)),
-                5 => adc2.sqr3.modify(|_, w| w.sq5().bits(input as u8)),
-                6 => adc2.sqr3.modify(|_, w| w.sq6().bits(input as u8)),
-                7 => adc2.sqr2.modify(|_, w| w.sq7().bits(input as u8)),
-                8 => adc2.sqr2.modify(|_, w| w.sq8().bits(input as u8)),
-                9 => adc2.sqr2.modify(|_, w| w.sq9().bits(input as u8)),
-                10 => adc2.sqr2.modify(|_, w| w.sq10().bits(input as u8)),
-                11 => adc2.sqr2.modify(|_, w| w.sq11().bits(input as u8)),
-                12 => adc2.sqr2.modify(|_, w| w.sq12().bits(input as u8)),
-                13 => adc2.sqr1.modify(|_, w| w.sq13().bits(input as u8)),
-                14 => adc2.sqr1.modify(|_, w| w.sq14().bits(input as u8)),
-                15 => adc2.sqr1.modify(|_, w| w.sq15().bits(input as u8)),
-                16 => adc2.sqr1.modify(|_, w| w.sq16().bits(input as u8)),
-                _ => panic!("invalid sequence register"),
-            }
-        }
-
-        // Use as many conversions as maximum channel sequence number
-        let l = adc2.sqr1.read().l().bits();
-        if l < sq {
-            adc2.sqr1
-                .modify(|_, w| unsafe { w.l().bits(sq.wrapping_sub(1)) });
-        }
+        self.set_sequence(input, sq);
+        self.enable_pin(input, gpioa, gpiob, gpioc);
+    }
 
-        // Set pins as analog input
-        match input {
-            AdcChannel::_0 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl0().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder0().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr0().bits(0b00) });
-            }
-            AdcChannel::_1 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl1().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder1().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr1().bits(0b00) });
-            }
-            AdcChannel::_2 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl2().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder2().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr2().bits(0b00) });
-            }
-            AdcChannel::_3 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl3().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder3().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr3().bits(0b00) });
-            }
-            AdcChannel::_4 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl4().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder4().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr4().bits(0b00) });
-            }
-            AdcChannel::_5 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl5().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder5().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr5().bits(0b00) });
-            }
-            AdcChannel::_6 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl6().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder6().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr6().bits(0b00) });
-            }
-            AdcChannel::_7 => {
-                gpioa.afrl.modify(|_, w| unsafe { w.afrl7().bits(0) });
-                gpioa.moder.modify(|_, w| unsafe { w.moder7().bits(0b11) });
-                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr7().bits(0b00) });
-            }
-            AdcChannel::_8 => unsafe {
-                gpiob.afrl.modify(|_, w| w.afrl0().bits(0));
-                gpiob.moder.modify(|_, w| w.moder0().bits(0b11));
-                gpiob.pupdr.modify(|_, w| w.pupdr0().bits(0b00));
-            },
-            AdcChannel::_9 => unsafe {
-                gpiob.afrl.modify(|_, w| w.afrl1().bits(0));
-                gpiob.moder.modify(|_, w| w.moder1().bits(0b11));
-                gpiob.pupdr.modify(|_, w| w.pupdr1().bits(0b00));
-            },
-            AdcChannel::_10 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl0().bits(0));
-                gpioc.moder.modify(|_, w| w.moder0().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr0().bits(0b00));
-            },
-            AdcChannel::_11 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl1().bits(0));
-                gpioc.moder.modify(|_, w| w.moder1().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr1().bits(0b00));
-            },
-            AdcChannel::_12 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl2().bits(0));
-                gpioc.moder.modify(|_, w| w.moder2().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr2().bits(0b00));
-            },
-            AdcChannel::_13 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl3().bits(0));
-                gpioc.moder.modify(|_, w| w.moder3().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr3().bits(0b00));
-            },
-            AdcChannel::_14 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl4().bits(0));
-                gpioc.moder.modify(|_, w| w.moder4().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr4().bits(0b00));
-            },
-            AdcChannel::_15 => unsafe {
-                gpioc.afrl.modify(|_, w| w.afrl5().bits(0));
-                gpioc.moder.modify(|_, w| w.moder5().bits(0b11));
-                gpioc.pupdr.modify(|_, w| w.pupdr5().bits(0b00));
-            },
-        }
+    /// Sets the pin of `input` as analog input without adding it to the scan
+    /// sequence, internal channels have no pin
+    pub fn enable_pin(&self, input: AdcChannel, gpioa: &GPIOA, gpiob: &GPIOB, gpioc: &GPIOC) {
+        set_analog_abc(input, gpioa, gpiob, gpioc);
     }
 
-    /// Initializes the ADC
+    /// Connects the internal channels
     ///
-    /// NOTE `Pwm<TIM2>.init` must be called before this method because both
-    /// methods configure the PA1 pin (one as input and the other as output :-/)
-    pub fn init(&self, dma2: &DMA2, rcc: &RCC) {
-        let adc2 = self.0;
-
-        // enable ADC2, DMA1, GPIOA, GPIOB, GPIOC, TIM2
-        rcc.ahb1enr.modify(|_, w| w.gpioaen().set_bit());
-        rcc.ahb1enr.modify(|_, w| w.gpioben().set_bit());
-        rcc.ahb1enr.modify(|_, w| w.gpiocen().set_bit());
-        rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
-        rcc.apb1enr.modify(|_, w| w.tim2en().set_bit());
-        rcc.apb2enr.modify(|_, w| w.adc2en().set_bit());
-
-        // RM0368 11.12.5
-        // Sample time: 55.5 + 12.5 = 68 cycles
-        adc2.smpr2.modify(|_, w| unsafe { w.smpx_x().bits(0) });
-
-        // ADC2
-        // chsel: Channel 0 (RM0368 9.3.3 Table 27)
-        // pl: Medium priority
-        // msize: Memory size = 16 bits
-        // psize: Peripheral size = 16 bits
-        // minc: Memory increment mode enabled
-        // pinc: Peripheral increment mode disabled
-        // circ: Circular mode enabled
-        // dir: Transfer from peripheral to memory
-        // htie: Half transfer interrupt enabled
-        // tceie: Transfer complete interrupt enabled
-        // en: Disabled
-        dma2.s0cr.write(|w| unsafe {
-            w.chsel()
-                .bits(0)
-                .pl()
-                .bits(0b01)
-                .msize()
-                .bits(0b01)
-                .psize()
-                .bits(0b01)
-                .minc()
-                .set_bit()
-                .circ()
-                .set_bit()
-                .pinc()
-                .clear_bit()
-                .dir()
-                .bits(0)
-                .htie()
-                .set_bit()
-                .tcie()
-                .set_bit()
-                .en()
-                .clear_bit()
-        });
-        // RM0368 11.12.3
-        // exten: Conversion on external trigger rising edge
-        // extsel: Timer 2 CC2 event
-        // align: Right alignment
-        // dma: DMA mode enabled
-        // dds: DMA requests are issued as long as data are converted and DMA=1
-        // cont: Single conversion mode
-        // adon: Disable ADC conversion
-        adc2.cr2.write(|w| unsafe {
-            w.exten()
-                .bits(0b01)
-                .extsel()
-                .bits(0b011) // T2C2
-                .align()
-                .clear_bit()
-                .dma()
-                .set_bit()
-                .dds()
-                .set_bit()
-                .cont()
-                .clear_bit()
-                .adon()
-                .clear_bit()
+    /// `tsvref` connects the temperature sensor and VREFINT, `vbat` connects
+    /// VBAT / 4. On the STM32F42x the temperature sensor and VBAT share IN18
+    /// and VBAT takes precedence, so enable only one of them at a time.
+    ///
+    /// The temperature sensor needs a sample time of at least 10 us, see
+    /// `set_sample_time`.
+    pub fn enable_internal(&self, common: &C_ADC, tsvref: bool, vbat: bool) {
+        // RM0368 11.12.17
+        // TSVREFE[23], VBATE[22]
+        common.ccr.modify(|r, w| unsafe {
+            w.bits(
+                (r.bits() & !(0b11 << 22)) | ((tsvref as u32) << 23) | ((vbat as u32) << 22),
+            )
         });
-        // RM0368 11.3.8 and 11.12.2
-        // scan: Scan mode enabled
-        adc2.cr1.write(|w| w.scan().set_bit());
     }
 
-    /// Disables the ADC
-    pub fn disable(&self) {
-        self.0.cr2.modify(|_, w| w.adon().clear_bit());
-    }
-
-    /// Enables the ADC
-    pub fn enable(&self) {
-        self.0.cr2.modify(|_, w| w.adon().set_bit());
-    }
-
-    /// Starts an analog to digital conversion that will be periodically
-    /// triggered by the channel 2 of TIM2
+    /// Starts moving multi ADC conversions, started by ADC1's trigger, to
+    /// memory
     ///
-    /// The conversions will be stored in the circular `buffer`
-    pub fn start<B>(
+    /// `set_multi_mode` must have been called with a DMA mode. The words of
+    /// ADC_CDR will be stored in the circular `buffer`: `u32` words for DMA
+    /// mode 2, `u16` for modes 1 and 3. The buffer must use one of ADC1's DMA2
+    /// streams.
+    pub fn start_multi<B, W, N>(
         &self,
-        buffer: &Static<CircBuffer<B, Dma2Stream0>>,
+        buffer: &Static<CircBuffer<B, Stream<DMA2, N>>>,
+        common: &C_ADC,
         dma2: &DMA2,
-        pwm: Pwm<TIM2>,
     ) -> Result<(), dma::Error>
     where
-        B: Unsize<[u16]>,
+        B: Unsize<[W]>,
+        W: MultiWord,
+        N: StreamNumber,
+        Stream<DMA2, N>: AdcStream<ADC1>,
     {
-        let adc2 = self.0;
-
-        if dma2.s0cr.read().en().bit_is_set() {
-            return Err(dma::Error::InUse);
+        match get_multi_dma(common) {
+            MultiDma::Disabled => panic!("multi ADC DMA mode disabled"),
+            MultiDma::Mode2 => assert_eq!(W::size(), Size::Word, "DMA mode 2 moves 32-bit words"),
+            _ => assert_eq!(W::size(), Size::HalfWord, "DMA modes 1 and 3 move 16-bit words"),
         }
 
-        pwm.disable(Channel::_2);
-        pwm.set_duty(Channel::_2, 1);
+        // the common DMA request replaces ADC1's own
+        self.set_dma(false);
 
-        let buffer: &[u16] = &buffer.lock()[0];
+        let config = dma_config(<Stream<DMA2, N> as AdcStream<ADC1>>::request(), W::size());
+        buffer.start::<W>(dma2, &config, &common.cdr as *const _ as u32)
+    }
+}
 
-        dma2.s0ndtr
-            .write(|w| unsafe { w.ndt().bits(u16(buffer.len() * 2).unwrap()) });
+impl<'a> Adc<'a, ADC2> {
+    /// Enables the ADC input and puts it at position `sq` (1 - 16) of the
+    /// scan sequence
+    pub fn enable_input(
+        &self,
+        input: AdcChannel,
+        sq: u8,
+        gpioa: &GPIOA,
+        gpiob: &GPIOB,
+        gpioc: &GPIOC,
+    ) {
+        self.set_sequence(input, sq);
+        self.enable_pin(input, gpioa, gpiob, gpioc);
+    }
 
-        dma2.s0par
-            .write(|w| unsafe { w.bits(&adc2.dr as *const _ as u32) });
+    /// Sets the pin of `input` as analog input without adding it to the scan
+    /// sequence
+    pub fn enable_pin(&self, input: AdcChannel, gpioa: &GPIOA, gpiob: &GPIOB, gpioc: &GPIOC) {
+        assert!((input as u8) < 16, "internal channels are only connected to ADC1");
 
-        dma2.s0m0ar
-            .write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+        set_analog_abc(input, gpioa, gpiob, gpioc);
+    }
+}
 
-        dma2.s0cr.modify(|_, w| w.en().set_bit());
-        pwm.enable(Channel::_2);
+impl<'a> Adc<'a, ADC3> {
+    /// Enables the ADC input and puts it at position `sq` (1 - 16) of the
+    /// scan sequence
+    ///
+    /// ADC3 has no inputs on port B, IN4 - IN9, IN14 and IN15 are on port F.
+    pub fn enable_input(
+        &self,
+        input: AdcChannel,
+        sq: u8,
+        gpioa: &GPIOA,
+        gpioc: &GPIOC,
+        gpiof: &GPIOF,
+    ) {
+        self.set_sequence(input, sq);
+        self.enable_pin(input, gpioa, gpioc, gpiof);
+    }
+
+    /// Sets the pin of `input` as analog input without adding it to the scan
+    /// sequence
+    pub fn enable_pin(&self, input: AdcChannel, gpioa: &GPIOA, gpioc: &GPIOC, gpiof: &GPIOF) {
+        assert!((input as u8) < 16, "internal channels are only connected to ADC1");
+
+        match input {
+            AdcChannel::_0 => analog!(gpioa, moder0, pupdr0),
+            AdcChannel::_1 => analog!(gpioa, moder1, pupdr1),
+            AdcChannel::_2 => analog!(gpioa, moder2, pupdr2),
+            AdcChannel::_3 => analog!(gpioa, moder3, pupdr3),
+            AdcChannel::_4 => analog!(gpiof, moder6, pupdr6),
+            AdcChannel::_5 => analog!(gpiof, moder7, pupdr7),
+            AdcChannel::_6 => analog!(gpiof, moder8, pupdr8),
+            AdcChannel::_7 => analog!(gpiof, moder9, pupdr9),
+            AdcChannel::_8 => analog!(gpiof, moder10, pupdr10),
+            AdcChannel::_9 => analog!(gpiof, moder3, pupdr3),
+            AdcChannel::_10 => analog!(gpioc, moder0, pupdr0),
+            AdcChannel::_11 => analog!(gpioc, moder1, pupdr1),
+            AdcChannel::_12 => analog!(gpioc, moder2, pupdr2),
+            AdcChannel::_13 => analog!(gpioc, moder3, pupdr3),
+            AdcChannel::_14 => analog!(gpiof, moder4, pupdr4),
+            AdcChannel::_15 => analog!(gpiof, moder5, pupdr5),
+            AdcChannel::_16 | AdcChannel::_17 | AdcChannel::_18 => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_channel_samples() {
+        // ranks: 4, 7, 4
+        let mut channels = [0; 16];
+        channels[..3].copy_from_slice(&[4, 7, 4]);
+        let sequence = Sequence {
+            channels: channels,
+            len: 3,
+        };
+        let samples = [10, 20, 30, 40, 50, 60];
+        let view = sequence.view(&samples);
+
+        assert_eq!(sequence.position(AdcChannel::_4), Some(0));
+        let mut ch4 = view.channel(AdcChannel::_4).unwrap();
+        for expected in &[10, 30, 40, 60] {
+            assert_eq!(ch4.next(), Some(*expected));
+        }
+        assert_eq!(ch4.next(), None);
+        assert_eq!(view.channel(AdcChannel::_7).unwrap().count(), 2);
+        assert!(view.channel(AdcChannel::_5).is_none());
 
-        Ok(())
+        let stats = view.stats(AdcChannel::_4).unwrap();
+        assert_eq!(stats.min, 10.);
+        assert_eq!(stats.max, 60.);
+        assert_eq!(stats.mean, 35.);
     }
 }
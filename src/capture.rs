@@ -19,12 +19,13 @@
 //! # TIM3
 //!
 //! - CH1 = PA6
-//! - CH2 = PA7
+//! - CH2 = PC7
 //! - CH3 = PB0
 //! - CH4 = PB1
 //!
-//! **WARNING** Do not use channels 3 and 4 with the `Capture.capture` API or
-//! you'll get junk values.
+//! **WARNING** Channels 3 and 4 have been reported to return junk values with
+//! the `Capture.capture` API. The cause hasn't been found yet: their register
+//! programming matches the other channels and timers.
 //!
 //! # TIM4
 //!
@@ -32,15 +33,55 @@
 //! - CH2 = PB7 (5V tolerant)
 //! - CH3 = PB8 (5V tolerant)
 //! - CH4 = PB9 (5V tolerant)
+//!
+//! # TIM5
+//!
+//! - CH1 = PA0
+//! - CH2 = PA1
+//! - CH3 = PA2 (Unimplemented: conflicts with USB USART2_TX)
+//! - CH4 = PA3 (Unimplemented: conflicts with USB USART2_RX)
+//!
+//! # Input configuration
+//!
+//! `init` applies the heaviest digital filter, no prescaler and rising edge
+//! capture. Use `Capture::configure` to change the filter, prescaler, active
+//! edge(s) and input mapping of each channel.
+//!
+//! # PWM input mode
+//!
+//! `Capture::init_pwm_input` measures the period and duty cycle of a PWM signal
+//! connected to channel 1 or 2. Both channels of the pair are used.
+//!
+//! # DMA capture
+//!
+//! On the 32-bit timers TIM2 and TIM5 `Capture::capture_dma` streams every
+//! captured CCRx value into a `CircBuffer` through DMA1. The buffer's stream
+//! selects the timer channel:
+//!
+//! - TIM2_CH1 = DMA1 stream 5, channel 3
+//! - TIM2_CH2 = DMA1 stream 6, channel 3
+//! - TIM2_CH3 = DMA1 stream 1, channel 3
+//! - TIM5_CH1 = DMA1 stream 2, channel 6
+//! - TIM5_CH2 = DMA1 stream 4, channel 6
+//!
+//! TIM2_CH4 and TIM5_CH3/CH4 have DMA requests too, but their pins are taken
+//! by USART2 (see above).
+//!
+//! `Edges` turns the timestamps of consecutive half-buffers into periods.
 
 use core::any::{Any, TypeId};
+use core::marker::Unsize;
 use core::u32;
 
 use cast::u32;
 use hal;
 use nb;
-use stm32f40x::{TIM1, TIM2, TIM3, TIM4, GPIOA, GPIOB, GPIOC, RCC};
+use static_ref::Static;
+use stm32f40x::{DMA1, TIM1, TIM2, TIM3, TIM4, TIM5, GPIOA, GPIOB, GPIOC, RCC};
 
+use dma::{self, CircBuffer, Config, Direction, Dma1Stream1, Dma1Stream2, Dma1Stream4,
+          Dma1Stream5, Dma1Stream6, DmaChannel, Flags, Priority, Size, Stream, StreamNumber};
+use time::{Hertz, Microseconds, Seconds};
 use timer::Channel;
 
 /// Input / capture error
@@ -64,6 +105,231 @@ pub enum Event {
     Capture4,
 }
 
+/// Active edge(s) of an input channel (CCxP / CCxNP bits of TIMx_CCER)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Polarity {
+    /// Capture on the rising edge
+    Rising,
+    /// Capture on the falling edge
+    Falling,
+    /// Capture on both edges
+    Both,
+}
+
+/// Number of events needed to trigger a capture (ICxPSC bits of TIMx_CCMRx)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Prescaler {
+    /// Capture on every event
+    _1 = 0b00,
+    /// Capture once every 2 events
+    _2 = 0b01,
+    /// Capture once every 4 events
+    _4 = 0b10,
+    /// Capture once every 8 events
+    _8 = 0b11,
+}
+
+/// Input the capture channel is wired to (CCxS bits of TIMx_CCMRx)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mapping {
+    /// ICx is mapped on its own input, e.g. IC1 on TI1
+    Direct = 0b01,
+    /// ICx is mapped on the input of the paired channel, e.g. IC1 on TI2 and
+    /// IC3 on TI4. The pin of the paired channel has to be initialized
+    Indirect = 0b10,
+}
+
+/// Configuration of an input channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InputConfig {
+    /// Digital filter, ICxF bits in the range `0 ..= 15`. `0` disables the
+    /// filter, `15` samples at fDTS / 32 and requires 8 equal samples
+    pub filter: u8,
+    /// Event prescaler
+    pub prescaler: Prescaler,
+    /// Active edge(s)
+    pub polarity: Polarity,
+    /// Input mapping
+    pub mapping: Mapping,
+}
+
+impl Default for InputConfig {
+    /// Heaviest filter, no prescaler, rising edge, direct mapping
+    fn default() -> Self {
+        InputConfig {
+            filter: 0b1111,
+            prescaler: Prescaler::_1,
+            polarity: Polarity::Rising,
+            mapping: Mapping::Direct,
+        }
+    }
+}
+
+/// TIMx_CCMRx and TIMx_CCER bits of an input channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct InputBits {
+    /// The channel is configured in CCMR2 rather than CCMR1
+    ccmr2: bool,
+    ccmr_mask: u32,
+    ccmr: u32,
+    /// CCxE
+    enable: u32,
+    /// CCxP and CCxNP
+    polarity_mask: u32,
+    polarity: u32,
+}
+
+fn input_bits(channel: Channel, config: &InputConfig) -> InputBits {
+    let n = match channel {
+        Channel::_1 => 0,
+        Channel::_2 => 1,
+        Channel::_3 => 2,
+        Channel::_4 => 3,
+    };
+
+    // RM0368 13.4.7, 13.4.8 and 13.4.9
+    // CCMRx: ICxF[7:4], ICxPSC[3:2], CCxS[1:0] for CH1 / CH3,
+    // shifted by 8 for CH2 / CH4
+    // CCER: CCxE at 4n, CCxP at 4n + 1, CCxNP at 4n + 3
+    let field = (u32(config.filter) << 4) | ((config.prescaler as u32) << 2) |
+        config.mapping as u32;
+    let shift = 8 * (n % 2);
+    let polarity: u32 = match config.polarity {
+        Polarity::Rising => 0b0000,
+        Polarity::Falling => 0b0010,
+        Polarity::Both => 0b1010,
+    };
+
+    InputBits {
+        ccmr2: n >= 2,
+        ccmr_mask: 0xff << shift,
+        ccmr: field << shift,
+        enable: 1 << (4 * n),
+        polarity_mask: 0b1010 << (4 * n),
+        polarity: polarity << (4 * n),
+    }
+}
+
+/// Period and duty cycle of a PWM signal, see `Capture::pwm_input`
+#[derive(Clone, Copy, Debug)]
+pub struct PwmInput<T> {
+    /// Time between two rising edges
+    pub period: T,
+    /// Time between a rising edge and the next falling edge
+    pub high: T,
+    /// `high / period`, in the range `0.0 ..= 1.0`
+    pub duty: f32,
+}
+
+/// Tracks edge timestamps across consecutive DMA half-buffers
+///
+/// The counter is expected to run over its full 32-bit range, differences
+/// between timestamps are computed with wrapping arithmetic.
+pub struct Edges {
+    last: Option<u32>,
+}
+
+impl Edges {
+    /// Creates a tracker that hasn't seen any edge yet
+    pub const fn new() -> Self {
+        Edges { last: None }
+    }
+
+    /// Forgets the last seen edge, e.g. after an overrun
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Writes the period between every timestamp and the previous one into
+    /// `periods`, in counter ticks
+    ///
+    /// The first timestamp ever seen has no predecessor and produces no
+    /// period. Returns the number of periods written; if `periods` fills up
+    /// the remaining periods are dropped, the next call measures from the
+    /// last timestamp of `timestamps`.
+    pub fn periods(&mut self, timestamps: &[u32], periods: &mut [u32]) -> usize {
+        let mut n = 0;
+        for ts in timestamps {
+            if let Some(last) = self.last {
+                if n == periods.len() {
+                    self.last = timestamps.last().cloned();
+                    break;
+                }
+                periods[n] = ts.wrapping_sub(last);
+                n += 1;
+            }
+            self.last = Some(*ts);
+        }
+        n
+    }
+
+    /// Returns the mean period of `timestamps`, in counter ticks
+    pub fn mean_period(&mut self, timestamps: &[u32]) -> Option<u32> {
+        let (first, n) = match self.last {
+            Some(last) => (last, timestamps.len()),
+            None => match timestamps.first() {
+                Some(first) => (*first, timestamps.len() - 1),
+                None => return None,
+            },
+        };
+
+        match timestamps.last() {
+            Some(last) => {
+                self.last = Some(*last);
+                if n == 0 {
+                    None
+                } else {
+                    Some(last.wrapping_sub(first) / n as u32)
+                }
+            }
+            None => None,
+        }
+    }
+}
+
+/// Converts a `period` in counter ticks into a frequency, `counter` is the
+/// counter clock (see `Capture::counter_frequency`)
+pub fn frequency(period: u32, counter: Hertz) -> f32 {
+    if period == 0 {
+        0.0
+    } else {
+        counter.0 as f32 / period as f32
+    }
+}
+
+/// DMA1 stream that moves the captures of a channel of `TIM`, see the
+/// module documentation
+pub trait CaptureStream<TIM> {
+    /// Returns the timer channel whose captures the stream moves
+    fn channel() -> Channel;
+    /// Returns the request channel (CHSEL) of the timer channel
+    fn request() -> DmaChannel;
+}
+
+macro_rules! capture_streams {
+    ($($TIM:ident: $STREAM:ident => $channel:ident, $request:ident;)+) => {
+        $(
+            impl CaptureStream<$TIM> for $STREAM {
+                fn channel() -> Channel {
+                    Channel::$channel
+                }
+
+                fn request() -> DmaChannel {
+                    DmaChannel::$request
+                }
+            }
+        )+
+    }
+}
+
+capture_streams! {
+    TIM2: Dma1Stream5 => _1, _3;
+    TIM2: Dma1Stream6 => _2, _3;
+    TIM2: Dma1Stream1 => _3, _3;
+    TIM5: Dma1Stream2 => _1, _6;
+    TIM5: Dma1Stream4 => _2, _6;
+}
+
 /// Input capture interface
 pub struct Capture<'a, T>(pub &'a T)
 where
@@ -83,7 +349,10 @@ macro_rules! impl_Capture {
         {
             /// Initializes the input capture interface
             ///
-            /// `resolution` is the resolution of the capture timer
+            /// `resolution` is the resolution of the capture timer. Changing
+            /// it resets the counter, which offsets the captures of channels
+            /// that are already running; initialize every channel of a timer
+            /// with the same resolution, or before starting to capture.
             pub fn init<R>(
                 &self,
                 resolution: R,
@@ -108,6 +377,56 @@ macro_rules! impl_Capture {
                 rcc: &RCC) {
                 let tim = self.0;
 
+                self._init_pins(channel, gpioa, gpiob, gpioc, rcc);
+
+                tim.smcr.write(|w| unsafe {
+                    w.bits(0)
+                });
+                // configure CCx as input and wire it to TIx, apply the heaviest
+                // filter. Only the bits of `channel` are touched so channels
+                // that are already capturing keep their configuration
+                self.configure(channel, InputConfig::default());
+
+                // capture pins disabled by default
+                match channel {
+                    Channel::_1 => {
+                        tim.ccer.modify(|_, w| {w.cc1p().clear_bit().cc1e().clear_bit()});
+                    }
+                    Channel::_2 => {
+                        tim.ccer.modify(|_, w| {w.cc2p().clear_bit().cc2e().clear_bit()});
+                    }
+                    Channel::_3 => {
+                        tim.ccer.modify(|_, w| {w.cc3p().clear_bit().cc3e().clear_bit()});
+                    }
+                    Channel::_4 => {
+                        if tim.get_type_id() == TypeId::of::<TIM2>() {
+                            panic!("Not implemented: conflicts with USB USART2_RX");
+                        }
+                        tim.ccer.modify(|_, w| {w.cc4p().clear_bit().cc4e().clear_bit()});
+                    }
+                }
+
+                self._set_resolution(resolution);
+
+                tim.arr.write(|w| unsafe{ w.bits(u32::MAX) });
+
+                // RM0368 13.4.1
+                // udis: Update event disabled, shadow registers keep their value (ARR, PSC, CCRx)
+                // dir: Upcounter
+                // opm: One-pulse mode disabled
+                // cen: Counter enabled
+                tim.cr1.write(|w| w.udis().set_bit().dir().bit(false).opm().bit(false).cen().set_bit());
+            }
+
+            fn _init_pins(
+                &self,
+                channel: Channel,
+                gpioa: &GPIOA,
+                gpiob: &GPIOB,
+                gpioc: &GPIOC,
+                rcc: &RCC) {
+                let tim = self.0;
+
                 if tim.get_type_id() == TypeId::of::<TIM1>() {
                     rcc.apb2enr.modify(|_, w| w.tim1en().set_bit());
                 } else if tim.get_type_id() == TypeId::of::<TIM2>() {
@@ -116,6 +435,8 @@ macro_rules! impl_Capture {
                     rcc.apb1enr.modify(|_, w| w.tim3en().set_bit());
                 } else if tim.get_type_id() == TypeId::of::<TIM4>() {
                     rcc.apb1enr.modify(|_, w| w.tim4en().set_bit());
+                } else if tim.get_type_id() == TypeId::of::<TIM5>() {
+                    rcc.apb1enr.modify(|_, w| w.tim5en().set_bit());
                 }
 
                 rcc.ahb1enr.modify(|_, w| {
@@ -137,6 +458,12 @@ macro_rules! impl_Capture {
                         }
                     } else if tim.get_type_id() == TypeId::of::<TIM4>() {
                         w.gpioben().set_bit()
+                    } else if tim.get_type_id() == TypeId::of::<TIM5>() {
+                        match channel {
+                            Channel::_1 =>  w.gpioaen().set_bit(),
+                            Channel::_2 =>  w.gpioaen().set_bit(),
+                            _ =>  panic!("Not implemented: conflicts with USB USART2"),
+                        }
                     } else {
                         unreachable!()
                     }
@@ -237,50 +564,259 @@ macro_rules! impl_Capture {
                             gpiob.moder.modify(|_, w| unsafe {w.moder9().bits(2)});
                         }
                     }
+                } else if tim.get_type_id() == TypeId::of::<TIM5>() {
+                    // CH1 = PA0 = alternate push-pull
+                    // CH2 = PA1 = alternate push-pull
+                    // CH3 = PA2 = alternate push-pull (Not implemented: conflicts with USB USART2_TX)
+                    // CH4 = PA3 = alternate push-pull (Not implemented: conflicts with USB USART2_RX)
+                    match channel {
+                        Channel::_1 => {
+                            gpioa.afrl.modify(|_, w| unsafe{ w.afrl0().bits(2)});
+                            gpioa.moder.modify(|_, w| unsafe{ w.moder0().bits(2)});
+                        }
+                        Channel::_2 => {
+                            gpioa.afrl.modify(|_, w| unsafe{ w.afrl1().bits(2)});
+                            gpioa.moder.modify(|_, w| unsafe{ w.moder1().bits(2)});
+                        }
+                        _ => {
+                            panic!("Not implemented: conflicts with USB USART2");
+                        }
+                    }
                 }
+            }
 
-                tim.smcr.write(|w| unsafe {
-                    w.bits(0)
-                });
-                // configure CC{1,2,3,4} as input and wire it to TI{1,2,3,4}
-                // apply the heaviest filter
-                tim.ccmr1_output.write(|w| unsafe {
-                    w.bits((0b1111 << 12) | (0b01 << 8) | (0b1111 << 4) | (0b01 << 0))
-                });
-                tim.ccmr2_output.write(|w| unsafe {
-                    w.bits((0b1111 << 12) | (0b01 << 8) | (0b1111 << 4) | (0b01 << 0))
-                });
+            /// Initializes PWM input mode on `channel`, which must be
+            /// `Channel::_1` or `Channel::_2`
+            ///
+            /// Both CC1 and CC2 capture the signal on the pin of `channel`:
+            /// the rising edge is captured on `channel` and resets the counter
+            /// (slave reset mode on TI1FP1 / TI2FP2), the falling edge is
+            /// captured on the other channel. Use `pwm_input` to read the
+            /// measurements.
+            pub fn init_pwm_input<R>(
+                &self,
+                resolution: R,
+                channel: Channel,
+                gpioa: &GPIOA,
+                gpiob: &GPIOB,
+                gpioc: &GPIOC,
+                rcc: &RCC)
+            where
+                R: Into<::$APB::Ticks>,
+            {
+                let tim = self.0;
 
-                // enable capture on rising edge
-                // capture pins disabled by default
+                self._init_pins(channel, gpioa, gpiob, gpioc, rcc);
+
+                // RM0368 13.3.6
+                // CC1S / CC2S: 0b01 = ICx mapped on TIx, 0b10 = ICx mapped on TIy
+                // TS: 0b101 = TI1FP1, 0b110 = TI2FP2
+                // SMS: 0b100 = Reset mode
+                let (ccmr1, ts) = match channel {
+                    Channel::_1 => ((0b10 << 8) | (0b01 << 0), 0b101),
+                    Channel::_2 => ((0b01 << 8) | (0b10 << 0), 0b110),
+                    _ => panic!("PWM input mode is only available on channels 1 and 2"),
+                };
+                tim.ccmr1_output.write(|w| unsafe { w.bits(ccmr1) });
+                tim.smcr.write(|w| unsafe { w.bits((ts << 4) | 0b100) });
+
+                // the rising edge is captured on `channel`, the falling edge
+                // on the other one
+                match channel {
+                    Channel::_1 => tim.ccer.modify(|_, w| {
+                        w.cc1p().clear_bit().cc2p().set_bit().cc1e().set_bit().cc2e().set_bit()
+                    }),
+                    _ => tim.ccer.modify(|_, w| {
+                        w.cc2p().clear_bit().cc1p().set_bit().cc1e().set_bit().cc2e().set_bit()
+                    }),
+                }
+
+                self._set_resolution(resolution.into());
+
+                tim.arr.write(|w| unsafe{ w.bits(u32::MAX) });
+
+                // RM0368 13.4.1
+                // udis: Update event disabled, shadow registers keep their value (ARR, PSC, CCRx)
+                // dir: Upcounter
+                // opm: One-pulse mode disabled
+                // cen: Counter enabled
+                tim.cr1.write(|w| w.udis().set_bit().dir().bit(false).opm().bit(false).cen().set_bit());
+            }
+
+            /// Returns the period and duty cycle measured in PWM input mode
+            ///
+            /// Returns `WouldBlock` until a new period has been captured and
+            /// `Overcapture` once if a period was missed since the last call,
+            /// the following calls wait for a fresh period. Times too long for
+            /// a `u32` number of microseconds saturate.
+            pub fn pwm_input(&self) -> nb::Result<PwmInput<Microseconds>, Error> {
+                let tim = self.0;
+                let sr = tim.sr.read();
+
+                // TS = TI1FP1: the period is captured on CC1
+                let ti1 = (tim.smcr.read().bits() >> 4) & 0b111 == 0b101;
+
+                let (overcapture, captured) = if ti1 {
+                    (sr.cc1of().bit_is_set(), sr.cc1if().bit_is_set())
+                } else {
+                    (sr.cc2of().bit_is_set(), sr.cc2if().bit_is_set())
+                };
+
+                if overcapture {
+                    // RM0368 13.4.5
+                    // Drop the stale captures (reading CCRx clears CCxIF) and
+                    // clear CCxOF of the period channel, rc_w0: writing 1
+                    // leaves the other flags alone
+                    tim.ccr1.read().bits();
+                    tim.ccr2.read().bits();
+                    let ccof = if ti1 { 1 << 9 } else { 1 << 10 };
+                    tim.sr.write(|w| unsafe { w.bits(!ccof) });
+
+                    Err(nb::Error::Other(Error::Overcapture))
+                } else if captured {
+                    // reading CCRx clears CCxIF
+                    let (period, high) = if ti1 {
+                        (tim.ccr1.read().bits(), tim.ccr2.read().bits())
+                    } else {
+                        (tim.ccr2.read().bits(), tim.ccr1.read().bits())
+                    };
+                    let duty = if period == 0 {
+                        0.0
+                    } else {
+                        high as f32 / period as f32
+                    };
+
+                    // counter ticks -> timer clock ticks -> microseconds, in
+                    // 64 bits since the 32-bit timers overflow u32 with any
+                    // prescaler
+                    let scale = u64::from(tim.psc.read().psc().bits()) + 1;
+                    let ticks_per_us = u64::from(::$APB::Ticks::from(Microseconds(1)).0);
+                    let to_us = |count: u32| {
+                        let us = u64::from(count) * scale / ticks_per_us;
+                        Microseconds(if us > u64::from(u32::MAX) {
+                            u32::MAX
+                        } else {
+                            us as u32
+                        })
+                    };
+
+                    Ok(PwmInput {
+                        period: to_us(period),
+                        high: to_us(high),
+                        duty: duty,
+                    })
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            /// Streams the values captured on the channel of `buffer`'s
+            /// stream into the circular `buffer` through DMA1
+            ///
+            /// Only TIM2 and TIM5 have streams, see the module documentation.
+            /// The channel must have been initialized with `init`; it is
+            /// enabled by this method.
+            pub fn capture_dma<B, N>(
+                &self,
+                dma1: &DMA1,
+                buffer: &Static<CircBuffer<B, Stream<DMA1, N>>>,
+                rcc: &RCC,
+            ) -> Result<(), dma::Error>
+            where
+                B: Unsize<[u32]>,
+                N: StreamNumber,
+                Stream<DMA1, N>: CaptureStream<$TIM>,
+            {
+                let tim = self.0;
+                let channel = <Stream<DMA1, N> as CaptureStream<$TIM>>::channel();
+
+                rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit());
+
+                let ccr = match channel {
+                    Channel::_1 => &tim.ccr1 as *const _ as u32,
+                    Channel::_2 => &tim.ccr2 as *const _ as u32,
+                    Channel::_3 => &tim.ccr3 as *const _ as u32,
+                    Channel::_4 => &tim.ccr4 as *const _ as u32,
+                };
+
+                buffer.start::<u32>(
+                    dma1,
+                    &Config {
+                        channel: <Stream<DMA1, N> as CaptureStream<$TIM>>::request(),
+                        priority: Priority::High,
+                        direction: Direction::PeripheralToMemory,
+                        msize: Size::Word,
+                        psize: Size::Word,
+                        minc: true,
+                        pinc: false,
+                        circular: true,
+                        interrupts: Flags {
+                            half_transfer: true,
+                            transfer_complete: true,
+                            ..Flags::default()
+                        },
+                    },
+                    ccr,
+                )?;
+
+                // Capture/Compare DMA request enable
                 match channel {
                     Channel::_1 => {
-                        tim.ccer.modify(|_, w| {w.cc1p().clear_bit().cc1e().clear_bit()});
+                        tim.dier.modify(|_, w| w.cc1de().set_bit());
+                        tim.ccer.modify(|_, w| w.cc1e().set_bit());
                     }
                     Channel::_2 => {
-                        tim.ccer.modify(|_, w| {w.cc2p().clear_bit().cc2e().clear_bit()});
+                        tim.dier.modify(|_, w| w.cc2de().set_bit());
+                        tim.ccer.modify(|_, w| w.cc2e().set_bit());
                     }
                     Channel::_3 => {
-                        tim.ccer.modify(|_, w| {w.cc3p().clear_bit().cc3e().clear_bit()});
+                        tim.dier.modify(|_, w| w.cc3de().set_bit());
+                        tim.ccer.modify(|_, w| w.cc3e().set_bit());
                     }
                     Channel::_4 => {
-                        if tim.get_type_id() == TypeId::of::<TIM2>() {
-                            panic!("Not implemented: conflicts with USB USART2_RX");
-                        }
-                        tim.ccer.modify(|_, w| {w.cc4p().clear_bit().cc4e().clear_bit()});
+                        tim.dier.modify(|_, w| w.cc4de().set_bit());
+                        tim.ccer.modify(|_, w| w.cc4e().set_bit());
                     }
                 }
 
-                self._set_resolution(resolution);
+                Ok(())
+            }
 
-                tim.arr.write(|w| unsafe{ w.bits(u32::MAX) });
+            /// Returns the frequency of the capture counter
+            pub fn counter_frequency(&self) -> Hertz {
+                let clock = ::$APB::Ticks::from(Seconds(1)).0;
+                Hertz(clock / (u32(self.0.psc.read().psc().bits()) + 1))
+            }
 
-                // RM0368 13.4.1
-                // udis: Update event disabled, shadow registers keep their value (ARR, PSC, CCRx)
-                // dir: Upcounter
-                // opm: One-pulse mode disabled
-                // cen: Counter enabled
-                tim.cr1.write(|w| w.udis().set_bit().dir().bit(false).opm().bit(false).cen().set_bit());
+            /// Changes the filter, prescaler, active edge(s) and input mapping
+            /// of `channel`
+            ///
+            /// The channel is briefly disabled because the mapping can only be
+            /// changed while capture is off; it's re-enabled afterwards if it
+            /// was enabled.
+            pub fn configure(&self, channel: Channel, config: InputConfig) {
+                let tim = self.0;
+
+                assert!(config.filter <= 0b1111, "invalid input filter");
+
+                let bits = input_bits(channel, &config);
+
+                let enabled = tim.ccer.read().bits() & bits.enable;
+                tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() & !bits.enable) });
+
+                if bits.ccmr2 {
+                    tim.ccmr2_output.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !bits.ccmr_mask) | bits.ccmr)
+                    });
+                } else {
+                    tim.ccmr1_output.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !bits.ccmr_mask) | bits.ccmr)
+                    });
+                }
+
+                tim.ccer.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !bits.polarity_mask) | bits.polarity | enabled)
+                });
             }
 
             /// Starts listening for an interrupt `event`
@@ -308,26 +844,52 @@ macro_rules! impl_Capture {
             }
 
             fn _set_resolution(&self, resolution: ::$APB::Ticks) {
+                let tim = self.0;
                 let psc = resolution.0.checked_sub(1).expect("impossible resolution");
 
-                self.0.psc.write(|w| unsafe{ w.bits(psc)});
+                // Every PSC write below is followed by an update, so PSC holds
+                // the active prescaler. Don't reset the counter of channels
+                // that are already capturing when nothing changes
+                if tim.psc.read().bits() == psc {
+                    return;
+                }
+
+                tim.psc.write(|w| unsafe{ w.bits(psc)});
+
+                // RM0368 13.4.11 and 13.4.6
+                // PSC is buffered and only takes effect on an update event,
+                // which never happens while UDIS is set. Without this the
+                // counter keeps running at the timer clock and the captured
+                // values don't match `resolution`. UG doesn't update the
+                // shadow registers while UDIS is set either, so lift it for
+                // the update. UG also resets CNT and the prescaler counter
+                let udis = tim.cr1.read().udis().bit_is_set();
+                tim.cr1.modify(|_, w| w.udis().clear_bit());
+                tim.egr.write(|w| w.ug().set_bit());
+                tim.cr1.modify(|_, w| w.udis().bit(udis));
             }
 
             /// Clear the overcapture bit of channel
             pub fn clear(&self, channel: Channel ) {
+                // RM0368 13.4.5
+                // The SR flags are rc_w0: writing 1 leaves them unchanged. A
+                // read-modify-write would clear the flags of the other
+                // channels that get set between the read and the write, and
+                // drop their captures
+                // CCxOF: bit 9 + x - 1
                 match channel {
                     Channel::_1 => {
                         self.0.ccr1.read().bits();
-                        self.0.sr.modify(|_,w|  w.cc1of().clear_bit() )},
+                        self.0.sr.write(|w| unsafe { w.bits(!(1 << 9)) })},
                     Channel::_2 => {
                         self.0.ccr2.read().bits();
-                        self.0.sr.modify(|_,w|  w.cc2of().clear_bit() )},
+                        self.0.sr.write(|w| unsafe { w.bits(!(1 << 10)) })},
                     Channel::_3 => {
                         self.0.ccr3.read().bits();
-                        self.0.sr.modify(|_,w|  w.cc3of().clear_bit() )},
+                        self.0.sr.write(|w| unsafe { w.bits(!(1 << 11)) })},
                     Channel::_4 => {
                         self.0.ccr4.read().bits();
-                        self.0.sr.modify(|_,w|  w.cc4of().clear_bit() )},
+                        self.0.sr.write(|w| unsafe { w.bits(!(1 << 12)) })},
                 }
             }
         }
@@ -394,7 +956,7 @@ macro_rules! impl_Capture {
             }
 
             fn get_resolution(&self) -> ::$APB::Ticks {
-                ::$APB::Ticks(u32(self.0.psc.read().psc().bits()))
+                ::$APB::Ticks(u32(self.0.psc.read().psc().bits()) + 1)
             }
 
             fn set_resolution<R>(&self, resolution: R)
@@ -411,3 +973,113 @@ impl_Capture!(TIM1, apb2);
 impl_Capture!(TIM2, apb1);
 impl_Capture!(TIM3, apb1);
 impl_Capture!(TIM4, apb1);
+impl_Capture!(TIM5, apb1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_bits_of_each_channel() {
+        let config = InputConfig::default();
+
+        // IC1F = 0b1111, IC1PSC = 0, CC1S = 0b01
+        let ch1 = input_bits(Channel::_1, &config);
+        assert!(!ch1.ccmr2);
+        assert_eq!((ch1.ccmr_mask, ch1.ccmr), (0x00ff, 0x00f1));
+        assert_eq!(ch1.enable, 1 << 0);
+
+        let ch2 = input_bits(Channel::_2, &config);
+        assert!(!ch2.ccmr2);
+        assert_eq!((ch2.ccmr_mask, ch2.ccmr), (0xff00, 0xf100));
+        assert_eq!(ch2.enable, 1 << 4);
+
+        // CH3 and CH4 live in CCMR2 at the same offsets as CH1 and CH2
+        let ch3 = input_bits(Channel::_3, &config);
+        assert!(ch3.ccmr2);
+        assert_eq!((ch3.ccmr_mask, ch3.ccmr), (0x00ff, 0x00f1));
+        assert_eq!(ch3.enable, 1 << 8);
+        assert_eq!((ch3.polarity_mask, ch3.polarity), (0b1010 << 8, 0));
+
+        let ch4 = input_bits(Channel::_4, &config);
+        assert!(ch4.ccmr2);
+        assert_eq!((ch4.ccmr_mask, ch4.ccmr), (0xff00, 0xf100));
+        assert_eq!(ch4.enable, 1 << 12);
+        assert_eq!((ch4.polarity_mask, ch4.polarity), (0b1010 << 12, 0));
+    }
+
+    #[test]
+    fn input_bits_of_a_config() {
+        let config = InputConfig {
+            filter: 0b0011,
+            prescaler: Prescaler::_4,
+            polarity: Polarity::Falling,
+            mapping: Mapping::Indirect,
+        };
+
+        let ch3 = input_bits(Channel::_3, &config);
+        assert_eq!(ch3.ccmr, 0b0011_10_10);
+        // CC3P
+        assert_eq!(ch3.polarity, 1 << 9);
+
+        let config = InputConfig {
+            polarity: Polarity::Both,
+            ..config
+        };
+        let ch4 = input_bits(Channel::_4, &config);
+        assert_eq!(ch4.ccmr, 0b0011_10_10 << 8);
+        // CC4P and CC4NP
+        assert_eq!(ch4.polarity, (1 << 13) | (1 << 15));
+    }
+
+    #[test]
+    fn periods_across_half_buffers() {
+        let mut edges = Edges::new();
+        let mut periods = [0; 4];
+
+        // the very first edge has no predecessor
+        assert_eq!(edges.periods(&[100, 150, 250], &mut periods), 2);
+        assert_eq!(&periods[..2], &[50, 100]);
+
+        // measured from the last edge of the previous half, across a wrap
+        assert_eq!(edges.periods(&[300, 0xffff_fff0, 0x10], &mut periods), 3);
+        assert_eq!(&periods[..3], &[50, 0xffff_fff0 - 300, 0x20]);
+
+        edges.reset();
+        assert_eq!(edges.periods(&[1000], &mut periods), 0);
+    }
+
+    #[test]
+    fn periods_dropped_when_full() {
+        let mut edges = Edges::new();
+        let mut periods = [0; 2];
+
+        assert_eq!(edges.periods(&[0, 10, 20, 30, 40], &mut periods), 2);
+        assert_eq!(periods, [10, 10]);
+
+        // the next period starts at the last timestamp, 40, not at 20
+        assert_eq!(edges.periods(&[45], &mut periods), 1);
+        assert_eq!(periods[0], 5);
+    }
+
+    #[test]
+    fn mean_period_across_half_buffers() {
+        let mut edges = Edges::new();
+
+        assert_eq!(edges.mean_period(&[]), None);
+        assert_eq!(edges.mean_period(&[100]), None);
+        // (400 - 100) / 3
+        assert_eq!(edges.mean_period(&[200, 300, 400]), Some(100));
+        // measured from the last edge of the previous half, across a wrap
+        edges.reset();
+        assert_eq!(edges.mean_period(&[0xffff_ff00]), None);
+        assert_eq!(edges.mean_period(&[0x0000_0000, 0x0000_0100]), Some(0x100));
+    }
+
+    #[test]
+    fn frequency_of_a_period() {
+        assert_eq!(frequency(0, Hertz(1_000_000)), 0.0);
+        assert_eq!(frequency(1000, Hertz(1_000_000)), 1000.0);
+        assert_eq!(frequency(3, Hertz(84_000_000)), 28_000_000.0);
+    }
+}
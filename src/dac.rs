@@ -0,0 +1,337 @@
+//! Digital to Analog Converter (DAC)
+//!
+//! Two 12-bit output channels
+//!
+//! - OUT1 = PA4
+//! - OUT2 = PA5
+//!
+//! # Conversions
+//!
+//! Without a trigger a value written with `Dac::write` appears on the pin one
+//! APB1 cycle later. With a trigger (`Dac::set_trigger`) the value is held
+//! until the next trigger event, which is also what advances the hardware
+//! `Wave` generators.
+//!
+//! # DMA
+//!
+//! `Dac::start` plays the two halves of a `CircBuffer` in a loop, one sample
+//! per trigger event, typically a timer TRGO. The buffer's stream selects the
+//! output channel:
+//!
+//! - OUT1: DMA1 Stream 5, channel 7
+//! - OUT2: DMA1 Stream 6, channel 7
+
+use core::marker::Unsize;
+
+use static_ref::Static;
+use stm32f40x::{DAC, DMA1, GPIOA, RCC};
+
+use dma::{self, CircBuffer, Config, Direction, Dma1Stream5, Dma1Stream6, DmaChannel, Flags,
+          Priority, Size, Stream, StreamNumber};
+
+/// DAC output channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DacChannel {
+    /// DAC_OUT1 = PA4
+    _1,
+    /// DAC_OUT2 = PA5
+    _2,
+}
+
+impl DacChannel {
+    /// Offset of the channel's bits in DAC_CR
+    fn shift(&self) -> u32 {
+        match *self {
+            DacChannel::_1 => 0,
+            DacChannel::_2 => 16,
+        }
+    }
+}
+
+/// Conversion trigger (TSELx bits of DAC_CR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    /// TIM6 TRGO event
+    Tim6Trgo = 0b000,
+    /// TIM8 TRGO event
+    Tim8Trgo = 0b001,
+    /// TIM7 TRGO event
+    Tim7Trgo = 0b010,
+    /// TIM5 TRGO event
+    Tim5Trgo = 0b011,
+    /// TIM2 TRGO event
+    Tim2Trgo = 0b100,
+    /// TIM4 TRGO event
+    Tim4Trgo = 0b101,
+    /// EXTI line 9
+    Exti9 = 0b110,
+    /// `Dac::trigger`
+    Software = 0b111,
+}
+
+/// Hardware wave generator (WAVEx and MAMPx bits of DAC_CR)
+///
+/// The generators need a trigger, every trigger event advances them by one
+/// step. Their output is added to the value written with `Dac::write`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Wave {
+    /// Generators disabled
+    Disabled,
+    /// Pseudo-random noise from an LFSR, unmasking the given number of low
+    /// bits (1 - 12)
+    Noise(u8),
+    /// Triangle from 0 up to 2 ^ n - 1 and back, n = 1 - 12
+    Triangle(u8),
+}
+
+/// DMA1 stream that serves a DAC channel
+pub trait DacStream {
+    /// Returns the channel the stream serves
+    fn channel() -> DacChannel;
+}
+
+impl DacStream for Dma1Stream5 {
+    fn channel() -> DacChannel {
+        DacChannel::_1
+    }
+}
+
+impl DacStream for Dma1Stream6 {
+    fn channel() -> DacChannel {
+        DacChannel::_2
+    }
+}
+
+/// Digital to Analog Converter
+pub struct Dac<'a>(pub &'a DAC);
+
+impl<'a> Dac<'a> {
+    /// Initializes `channel`, without trigger and with the output buffer
+    /// enabled
+    pub fn init(&self, channel: DacChannel, gpioa: &GPIOA, rcc: &RCC) {
+        let dac = self.0;
+
+        rcc.ahb1enr.modify(|_, w| w.gpioaen().set_bit());
+        rcc.apb1enr.modify(|_, w| w.dacen().set_bit());
+
+        // Analog mode avoids parasitic consumption on the pin
+        match channel {
+            DacChannel::_1 => {
+                gpioa.moder.modify(|_, w| unsafe { w.moder4().bits(0b11) });
+                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr4().bits(0b00) });
+            }
+            DacChannel::_2 => {
+                gpioa.moder.modify(|_, w| unsafe { w.moder5().bits(0b11) });
+                gpioa.pupdr.modify(|_, w| unsafe { w.pupdr5().bits(0b00) });
+            }
+        }
+
+        // RM0090 14.5.1
+        // Clear all of the channel's bits: output buffer enabled (BOFF = 0),
+        // no trigger, no wave, no DMA, disabled
+        let shift = channel.shift();
+        dac.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0xffff << shift)) });
+        self.write(channel, 0);
+    }
+
+    /// Enables `channel`
+    pub fn enable(&self, channel: DacChannel) {
+        let shift = channel.shift();
+        // EN
+        self.0
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << shift)) });
+    }
+
+    /// Disables `channel`
+    pub fn disable(&self, channel: DacChannel) {
+        let shift = channel.shift();
+        self.0
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << shift)) });
+    }
+
+    /// Enables or disables the output buffer of `channel`
+    ///
+    /// The buffer lowers the output impedance, without it the output can get
+    /// closer to 0 V and VREF+.
+    pub fn set_buffer(&self, channel: DacChannel, enable: bool) {
+        let shift = channel.shift();
+        // BOFF[1]
+        self.0.cr.modify(|r, w| unsafe {
+            if enable {
+                w.bits(r.bits() & !(1 << (shift + 1)))
+            } else {
+                w.bits(r.bits() | (1 << (shift + 1)))
+            }
+        });
+    }
+
+    /// Selects the trigger of `channel`, `None` updates the output as soon as
+    /// it's written
+    pub fn set_trigger(&self, channel: DacChannel, trigger: Option<Trigger>) {
+        let shift = channel.shift();
+
+        // TEN[2], TSEL[5:3]
+        let bits = match trigger {
+            Some(trigger) => ((trigger as u32) << 3) | (1 << 2),
+            None => 0,
+        };
+        self.0.cr.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b1111 << (shift + 2))) | (bits << shift))
+        });
+    }
+
+    /// Selects the wave generator of `channel`, which must have a trigger
+    pub fn set_wave(&self, channel: DacChannel, wave: Wave) {
+        let shift = channel.shift();
+
+        // WAVE[7:6], MAMP[11:8]
+        let bits = match wave {
+            Wave::Disabled => 0,
+            Wave::Noise(n) => {
+                assert!(n >= 1 && n <= 12, "invalid noise width");
+                (u32::from(n - 1) << 8) | (0b01 << 6)
+            }
+            Wave::Triangle(n) => {
+                assert!(n >= 1 && n <= 12, "invalid triangle amplitude");
+                (u32::from(n - 1) << 8) | (0b10 << 6)
+            }
+        };
+        self.0.cr.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11_1100_0000 << shift)) | (bits << shift))
+        });
+    }
+
+    /// Writes a 12-bit right aligned value to `channel`
+    pub fn write(&self, channel: DacChannel, value: u16) {
+        let dac = self.0;
+
+        assert!(value <= 0xfff, "invalid DAC value");
+
+        match channel {
+            DacChannel::_1 => dac.dhr12r1
+                .write(|w| unsafe { w.bits(u32::from(value)) }),
+            DacChannel::_2 => dac.dhr12r2
+                .write(|w| unsafe { w.bits(u32::from(value)) }),
+        }
+    }
+
+    /// Writes 12-bit right aligned values to both channels at once
+    pub fn write_both(&self, value1: u16, value2: u16) {
+        assert!(value1 <= 0xfff && value2 <= 0xfff, "invalid DAC value");
+
+        self.0.dhr12rd.write(|w| unsafe {
+            w.bits(u32::from(value1) | (u32::from(value2) << 16))
+        });
+    }
+
+    /// Returns the value currently output by `channel`
+    pub fn read(&self, channel: DacChannel) -> u16 {
+        match channel {
+            DacChannel::_1 => self.0.dor1.read().bits() as u16,
+            DacChannel::_2 => self.0.dor2.read().bits() as u16,
+        }
+    }
+
+    /// Generates a software trigger event on `channel`, which must use
+    /// `Trigger::Software`
+    pub fn trigger(&self, channel: DacChannel) {
+        // RM0090 14.5.2
+        // SWTRIG1[0], SWTRIG2[1]
+        let bit = match channel {
+            DacChannel::_1 => 1 << 0,
+            DacChannel::_2 => 1 << 1,
+        };
+        self.0.swtrigr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    /// Starts playing `buffer` on the channel of its DMA1 stream, one 12-bit
+    /// right aligned sample per trigger event
+    ///
+    /// The channel must have a trigger. Both halves are played in turn until
+    /// `stop` is called.
+    pub fn start<B, N>(
+        &self,
+        buffer: &Static<CircBuffer<B, Stream<DMA1, N>>>,
+        dma1: &DMA1,
+        rcc: &RCC,
+    ) -> Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+        N: StreamNumber,
+        Stream<DMA1, N>: DacStream,
+    {
+        let dac = self.0;
+        let channel = <Stream<DMA1, N> as DacStream>::channel();
+
+        rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit());
+
+        let dhr = match channel {
+            DacChannel::_1 => &dac.dhr12r1 as *const _ as u32,
+            DacChannel::_2 => &dac.dhr12r2 as *const _ as u32,
+        };
+
+        // Channel 7 (see module documentation)
+        buffer.start::<u16>(
+            dma1,
+            &Config {
+                channel: DmaChannel::_7,
+                priority: Priority::Medium,
+                direction: Direction::MemoryToPeripheral,
+                msize: Size::HalfWord,
+                psize: Size::HalfWord,
+                minc: true,
+                pinc: false,
+                circular: true,
+                interrupts: Flags {
+                    half_transfer: true,
+                    transfer_complete: true,
+                    ..Flags::default()
+                },
+            },
+            dhr,
+        )?;
+
+        // DMAEN[12]
+        let shift = channel.shift();
+        dac.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << (shift + 12))) });
+
+        Ok(())
+    }
+
+    /// Stops playing `buffer`, the output keeps its last value
+    ///
+    /// Disables the DMA requests of the channel and its DMA1 stream, after
+    /// which `start` can be called again.
+    pub fn stop<B, N>(&self, buffer: &Static<CircBuffer<B, Stream<DMA1, N>>>, dma1: &DMA1)
+    where
+        N: StreamNumber,
+        Stream<DMA1, N>: DacStream,
+    {
+        let shift = <Stream<DMA1, N> as DacStream>::channel().shift();
+        self.0
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (shift + 12))) });
+
+        buffer.stop(dma1);
+    }
+
+    /// Returns `true` if `channel` missed a trigger because the DMA didn't
+    /// deliver a sample in time, clearing the flag
+    pub fn underrun(&self, channel: DacChannel) -> bool {
+        let dac = self.0;
+
+        // RM0090 14.5.15
+        // DMAUDR1[13], DMAUDR2[29]
+        let bit = 1 << (channel.shift() + 13);
+        if dac.sr.read().bits() & bit != 0 {
+            dac.sr.write(|w| unsafe { w.bits(bit) });
+            true
+        } else {
+            false
+        }
+    }
+}
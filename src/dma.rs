@@ -1,11 +1,23 @@
 //! Direct Memory Access (DMA)
+//!
+//! Each of the two controllers has 8 streams, `Stream<DMA1, S5>` (alias
+//! `Dma1Stream5`) etc. A stream is selected at compile time through the type
+//! of the `Buffer` / `CircBuffer` it's associated to; its registers can be
+//! programmed directly with `Stream::configure` and friends for peripherals
+//! that have no driver in this crate.
+//!
+//! `DoubleBuffer` runs a stream in hardware double buffer mode and
+//! `MemoryTransfer` copies or fills memory with a DMA2 stream, which is the
+//! only controller that can do memory to memory transfers.
+//!
+//! See RM0090 Table 42 and 43 for the stream / channel request mapping.
 
 use core::cell::{Cell, UnsafeCell};
-use core::marker::PhantomData;
-use core::ops;
+use core::marker::{PhantomData, Unsize};
+use core::{cmp, mem, ops, ptr};
 
 use nb;
-use stm32f40x::{DMA1, DMA2};
+use stm32f40x::{DMA1, DMA2, RCC};
 
 /// DMA error
 #[derive(Debug)]
@@ -17,47 +29,468 @@ pub enum Error {
     Overrun,
     /// Transfer error
     Transfer,
+    /// Address or length not aligned to the transfer width or burst
+    Misaligned,
+    /// Source and destination overlap
+    Overlap,
+    /// Source and destination lengths differ, or are zero
+    Length,
 }
 
-/// Stream 1 of DMA1
-pub struct Dma1Stream1 {
-    _0: (),
+/// DMA controller, `DMA1` or `DMA2`
+///
+/// # Safety
+///
+/// Implementors must be the register block of a DMA controller, streams
+/// access their registers at fixed offsets from it.
+pub unsafe trait Controller {}
+
+unsafe impl Controller for DMA1 {}
+unsafe impl Controller for DMA2 {}
+
+/// Stream number, one of `S0` - `S7`
+pub trait StreamNumber {
+    /// Returns the stream number, 0 - 7
+    fn number() -> usize;
 }
 
-/// Stream 2 of DMA1
-pub struct Dma1Stream2 {
-    _0: (),
+macro_rules! stream_numbers {
+    ($($S:ident: $n:expr, $doc:expr;)+) => {
+        $(
+            #[doc = $doc]
+            pub struct $S {
+                _0: (),
+            }
+
+            impl StreamNumber for $S {
+                fn number() -> usize {
+                    $n
+                }
+            }
+        )+
+    }
 }
 
-/// Stream 4 of DMA1
-pub struct Dma1Stream4 {
-    _0: (),
+stream_numbers! {
+    S0: 0, "Stream 0";
+    S1: 1, "Stream 1";
+    S2: 2, "Stream 2";
+    S3: 3, "Stream 3";
+    S4: 4, "Stream 4";
+    S5: 5, "Stream 5";
+    S6: 6, "Stream 6";
+    S7: 7, "Stream 7";
 }
 
-/// Stream 5 of DMA1
-pub struct Dma1Stream5 {
-    _0: (),
+/// Stream `N` of the controller `DMA`
+pub struct Stream<DMA, N> {
+    _marker: PhantomData<(DMA, N)>,
 }
 
+/// Stream 0 of DMA1
+pub type Dma1Stream0 = Stream<DMA1, S0>;
+/// Stream 1 of DMA1
+pub type Dma1Stream1 = Stream<DMA1, S1>;
+/// Stream 2 of DMA1
+pub type Dma1Stream2 = Stream<DMA1, S2>;
+/// Stream 3 of DMA1
+pub type Dma1Stream3 = Stream<DMA1, S3>;
+/// Stream 4 of DMA1
+pub type Dma1Stream4 = Stream<DMA1, S4>;
+/// Stream 5 of DMA1
+pub type Dma1Stream5 = Stream<DMA1, S5>;
 /// Stream 6 of DMA1
-pub struct Dma1Stream6 {
-    _0: (),
+pub type Dma1Stream6 = Stream<DMA1, S6>;
+/// Stream 7 of DMA1
+pub type Dma1Stream7 = Stream<DMA1, S7>;
+/// Stream 0 of DMA2
+pub type Dma2Stream0 = Stream<DMA2, S0>;
+/// Stream 1 of DMA2
+pub type Dma2Stream1 = Stream<DMA2, S1>;
+/// Stream 2 of DMA2
+pub type Dma2Stream2 = Stream<DMA2, S2>;
+/// Stream 3 of DMA2
+pub type Dma2Stream3 = Stream<DMA2, S3>;
+/// Stream 4 of DMA2
+pub type Dma2Stream4 = Stream<DMA2, S4>;
+/// Stream 5 of DMA2
+pub type Dma2Stream5 = Stream<DMA2, S5>;
+/// Stream 6 of DMA2
+pub type Dma2Stream6 = Stream<DMA2, S6>;
+/// Stream 7 of DMA2
+pub type Dma2Stream7 = Stream<DMA2, S7>;
+
+/// Channel request selection (CHSEL bits of DMA_SxCR), see RM0090 10.3.3
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DmaChannel {
+    /// Channel 0
+    _0 = 0,
+    /// Channel 1
+    _1 = 1,
+    /// Channel 2
+    _2 = 2,
+    /// Channel 3
+    _3 = 3,
+    /// Channel 4
+    _4 = 4,
+    /// Channel 5
+    _5 = 5,
+    /// Channel 6
+    _6 = 6,
+    /// Channel 7
+    _7 = 7,
 }
 
-/// Stream 0 of DMA2
-pub struct Dma2Stream0 {
-    _0: (),
+/// Stream priority (PL bits of DMA_SxCR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Priority {
+    /// Low
+    Low = 0b00,
+    /// Medium
+    Medium = 0b01,
+    /// High
+    High = 0b10,
+    /// Very high
+    VeryHigh = 0b11,
 }
 
-/// Stream 2 of DMA2
-pub struct Dma2Stream1 {
-    _0: (),
+/// Data size (MSIZE / PSIZE bits of DMA_SxCR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Size {
+    /// 8 bits
+    Byte = 0b00,
+    /// 16 bits
+    HalfWord = 0b01,
+    /// 32 bits
+    Word = 0b10,
 }
 
-/// Stream 2 of DMA2
-pub struct Dma2Stream6 {
-    _0: (),
+/// Transfer direction (DIR bits of DMA_SxCR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// From PAR to M0AR
+    PeripheralToMemory = 0b00,
+    /// From M0AR to PAR
+    MemoryToPeripheral = 0b01,
+    /// From PAR to M0AR, DMA2 only
+    MemoryToMemory = 0b10,
+}
+
+/// Burst transfer size (MBURST / PBURST bits of DMA_SxCR)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Burst {
+    /// Single transfer
+    Single = 0b00,
+    /// Incremental burst of 4 beats
+    Incr4 = 0b01,
+    /// Incremental burst of 8 beats
+    Incr8 = 0b10,
+    /// Incremental burst of 16 beats
+    Incr16 = 0b11,
+}
+
+impl Burst {
+    /// Returns the number of beats of a burst
+    pub fn beats(&self) -> usize {
+        match *self {
+            Burst::Single => 1,
+            Burst::Incr4 => 4,
+            Burst::Incr8 => 8,
+            Burst::Incr16 => 16,
+        }
+    }
+}
+
+/// Status flags of a stream, also used to select interrupts
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flags {
+    /// Transfer complete
+    pub transfer_complete: bool,
+    /// Half transfer
+    pub half_transfer: bool,
+    /// Transfer error
+    pub transfer_error: bool,
+    /// Direct mode error
+    pub direct_mode_error: bool,
+    /// FIFO overrun / underrun, not an interrupt source of DMA_SxCR
+    pub fifo_error: bool,
+}
+
+impl Flags {
+    /// All flags set
+    pub fn all() -> Self {
+        Flags {
+            transfer_complete: true,
+            half_transfer: true,
+            transfer_error: true,
+            direct_mode_error: true,
+            fifo_error: true,
+        }
+    }
+
+    /// Only the transfer complete flag set
+    pub fn transfer_complete() -> Self {
+        Flags {
+            transfer_complete: true,
+            ..Flags::default()
+        }
+    }
+
+    /// Only the half transfer flag set
+    pub fn half_transfer() -> Self {
+        Flags {
+            half_transfer: true,
+            ..Flags::default()
+        }
+    }
+
+    /// Bits of a stream's flags in DMA_xISR / DMA_xIFCR, before shifting
+    fn bits(&self) -> u32 {
+        ((self.fifo_error as u32) << 0) | ((self.direct_mode_error as u32) << 2) |
+            ((self.transfer_error as u32) << 3) | ((self.half_transfer as u32) << 4) |
+            ((self.transfer_complete as u32) << 5)
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        Flags {
+            fifo_error: bits & (1 << 0) != 0,
+            direct_mode_error: bits & (1 << 2) != 0,
+            transfer_error: bits & (1 << 3) != 0,
+            half_transfer: bits & (1 << 4) != 0,
+            transfer_complete: bits & (1 << 5) != 0,
+        }
+    }
+}
+
+/// Stream configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Request channel
+    pub channel: DmaChannel,
+    /// Priority
+    pub priority: Priority,
+    /// Direction
+    pub direction: Direction,
+    /// Memory data size
+    pub msize: Size,
+    /// Peripheral data size
+    pub psize: Size,
+    /// Increment the memory address after each transfer
+    pub minc: bool,
+    /// Increment the peripheral address after each transfer
+    pub pinc: bool,
+    /// Reload the transfer count and addresses at the end of the transfer
+    pub circular: bool,
+    /// Interrupts to enable, `fifo_error` is ignored
+    pub interrupts: Flags,
+}
+
+impl Default for Config {
+    /// Channel 0, medium priority, 8-bit peripheral to memory transfers into
+    /// an incremented buffer, no interrupts
+    fn default() -> Self {
+        Config {
+            channel: DmaChannel::_0,
+            priority: Priority::Medium,
+            direction: Direction::PeripheralToMemory,
+            msize: Size::Byte,
+            psize: Size::Byte,
+            minc: true,
+            pinc: false,
+            circular: false,
+            interrupts: Flags::default(),
+        }
+    }
+}
+
+// RM0090 10.5.11 DMA register map
+// DMA_LISR 0x00, DMA_HISR 0x04, DMA_LIFCR 0x08, DMA_HIFCR 0x0C
+// DMA_SxCR 0x10 + 0x18 * x, followed by NDTR, PAR, M0AR, M1AR and FCR
+const ISR: usize = 0x00;
+const IFCR: usize = 0x08;
+const STREAMS: usize = 0x10;
+const STREAM_SIZE: usize = 0x18;
+const CR: usize = 0x00;
+const NDTR: usize = 0x04;
+const PAR: usize = 0x08;
+const M0AR: usize = 0x0c;
+const M1AR: usize = 0x10;
+const FCR: usize = 0x14;
+/// Size of the register block
+const BLOCK_SIZE: usize = STREAMS + 8 * STREAM_SIZE;
+
+/// Position of each stream's flags within DMA_LISR / DMA_HISR
+const FLAG_SHIFT: [u32; 4] = [0, 6, 16, 22];
+
+/// Returns the register at `offset` of the register block of `dma`
+///
+/// svd2rust generates one field per stream register (`s0cr` .. `s7fcr`)
+/// which can't be indexed by the stream number, so `Stream` computes the
+/// offsets from the register map instead. This is the only place where they
+/// are turned into addresses; `tests::register_offsets` checks them against
+/// the svd2rust register block.
+fn register<D>(dma: &D, offset: usize) -> *mut u32
+where
+    D: Controller,
+{
+    assert!(offset < BLOCK_SIZE && offset % 4 == 0);
+
+    (dma as *const D as usize + offset) as *mut u32
+}
+
+impl<D, N> Stream<D, N>
+where
+    D: Controller,
+    N: StreamNumber,
+{
+    /// Offset of the stream register at `offset` within the register block
+    fn offset(offset: usize) -> usize {
+        STREAMS + STREAM_SIZE * N::number() + offset
+    }
+
+    /// Offset of the stream's DMA_xISR or DMA_xIFCR and its flags shift
+    fn flag_offset(offset: usize) -> (usize, u32) {
+        let n = N::number();
+        let high = if n < 4 { 0 } else { 4 };
+        (offset + high, FLAG_SHIFT[n % 4])
+    }
+
+    fn read(dma: &D, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(register(dma, Self::offset(offset))) }
+    }
+
+    fn write(dma: &D, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(register(dma, Self::offset(offset)), value) }
+    }
+
+    fn modify<F>(dma: &D, offset: usize, f: F)
+    where
+        F: FnOnce(u32) -> u32,
+    {
+        let value = Self::read(dma, offset);
+        Self::write(dma, offset, f(value));
+    }
+
+    /// Configures the stream, which must be disabled
+    ///
+    /// Leaves double buffer mode and bursts at their reset values and puts
+    /// the stream back in direct mode.
+    pub fn configure(dma: &D, config: &Config) {
+        assert!(!Self::is_enabled(dma), "DMA stream in use");
+
+        // RM0090 10.5.5
+        // CHSEL[27:25], PL[17:16], MSIZE[14:13], PSIZE[12:11], MINC[10],
+        // PINC[9], CIRC[8], DIR[7:6], TCIE[4], HTIE[3], TEIE[2], DMEIE[1]
+        let interrupts = config.interrupts;
+        Self::write(
+            dma,
+            CR,
+            ((config.channel as u32) << 25) | ((config.priority as u32) << 16) |
+                ((config.msize as u32) << 13) | ((config.psize as u32) << 11) |
+                ((config.minc as u32) << 10) | ((config.pinc as u32) << 9) |
+                ((config.circular as u32) << 8) | ((config.direction as u32) << 6) |
+                ((interrupts.transfer_complete as u32) << 4) |
+                ((interrupts.half_transfer as u32) << 3) |
+                ((interrupts.transfer_error as u32) << 2) |
+                ((interrupts.direct_mode_error as u32) << 1),
+        );
+
+        // RM0090 10.5.10
+        // Reset value: direct mode, FTH half full, which a previous memory to
+        // memory transfer may have changed
+        Self::write(dma, FCR, 0x21);
+    }
+
+    /// Sets the peripheral address (DMA_SxPAR)
+    pub fn set_peripheral_address(dma: &D, address: u32) {
+        Self::write(dma, PAR, address);
+    }
+
+    /// Sets the memory address (DMA_SxM0AR)
+    pub fn set_memory_address(dma: &D, address: u32) {
+        Self::write(dma, M0AR, address);
+    }
+
+    /// Sets the second memory address (DMA_SxM1AR), used in double buffer
+    /// mode
+    pub fn set_memory1_address(dma: &D, address: u32) {
+        Self::write(dma, M1AR, address);
+    }
+
+    /// Enables or disables double buffer mode, the stream must be disabled
+    pub fn set_double_buffer(dma: &D, enable: bool) {
+        // DBM[18], CT[19]: start with M0AR
+        Self::modify(dma, CR, |cr| if enable {
+            (cr | (1 << 18)) & !(1 << 19)
+        } else {
+            cr & !(1 << 18)
+        });
+    }
+
+    /// Enables or disables circular mode, the stream must be disabled
+    pub fn set_circular(dma: &D, enable: bool) {
+        // CIRC[8]
+        Self::modify(dma, CR, |cr| if enable { cr | (1 << 8) } else { cr & !(1 << 8) });
+    }
+
+    /// Returns the memory the stream is currently accessing in double buffer
+    /// mode, 0 for M0AR and 1 for M1AR
+    pub fn current_target(dma: &D) -> usize {
+        // CT[19]
+        ((Self::read(dma, CR) >> 19) & 1) as usize
+    }
+
+    /// Sets the number of data items to transfer (DMA_SxNDTR)
+    pub fn set_transfer_count(dma: &D, count: u16) {
+        Self::write(dma, NDTR, u32::from(count));
+    }
+
+    /// Returns the number of data items left to transfer
+    pub fn transfer_count(dma: &D) -> u16 {
+        Self::read(dma, NDTR) as u16
+    }
+
+    /// Enables the stream, clearing its flags first
+    pub fn enable(dma: &D) {
+        Self::clear_flags(dma, Flags::all());
+        Self::modify(dma, CR, |cr| cr | 1);
+    }
+
+    /// Disables the stream and waits until the current transfer stops
+    pub fn disable(dma: &D) {
+        Self::modify(dma, CR, |cr| cr & !1);
+        while Self::is_enabled(dma) {}
+    }
+
+    /// Returns `true` if the stream is enabled
+    pub fn is_enabled(dma: &D) -> bool {
+        Self::read(dma, CR) & 1 != 0
+    }
+
+    /// Points the stream at `peripheral` and `memory`, sets the number of
+    /// data items to transfer and enables it
+    ///
+    /// The stream must have been configured with `configure`.
+    pub fn start(dma: &D, peripheral: u32, memory: u32, count: u16) {
+        Self::set_peripheral_address(dma, peripheral);
+        Self::set_memory_address(dma, memory);
+        Self::set_transfer_count(dma, count);
+        Self::enable(dma);
+    }
+
+    /// Returns the stream's status flags
+    pub fn flags(dma: &D) -> Flags {
+        let (isr, shift) = Self::flag_offset(ISR);
+        Flags::from_bits(unsafe { ptr::read_volatile(register(dma, isr)) } >> shift)
+    }
+
+    /// Clears the selected status flags
+    pub fn clear_flags(dma: &D, flags: Flags) {
+        let (ifcr, shift) = Self::flag_offset(IFCR);
+        unsafe { ptr::write_volatile(register(dma, ifcr), flags.bits() << shift) }
+    }
 }
+
 /// Buffer to be used with a certain DMA `STREAM`
 // NOTE(packed) workaround for rust-lang/rust#41315
 #[repr(packed)]
@@ -188,6 +621,11 @@ impl<T, STREAM> Buffer<T, STREAM> {
         }
     }
 
+    /// Returns `true` if the buffer is locked by a DMA transfer
+    pub(crate) fn is_locked(&self) -> bool {
+        self.state.get() != State::Unlocked
+    }
+
     pub(crate) fn lock(&self) -> &T {
         assert_eq!(self.state.get(), State::Unlocked);
         unsafe { assert_ne!(self.flag.get(), WRITING) };
@@ -219,66 +657,56 @@ impl<T, STREAM> Buffer<T, STREAM> {
     }
 }
 
-// FIXME these `release` methods probably want some of sort of barrier
-impl<T> Buffer<T, Dma1Stream2> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &DMA1) -> nb::Result<(), Error> {
-        let state = self.state.get();
-
-        if state == State::Unlocked {
-            return Ok(());
+// FIXME `release` probably wants some of sort of barrier
+impl<T, D, N> Buffer<T, Stream<D, N>>
+where
+    D: Controller,
+    N: StreamNumber,
+{
+    /// Starts transferring the first `len` items of the buffer to or from
+    /// the register at `peripheral`, on a stream configured with
+    /// `Stream::configure`
+    ///
+    /// The buffer stays locked until it's `release`d. Returns `InUse` while
+    /// the stream or the buffer are still in use and `Length` if `len` is
+    /// zero or longer than the buffer or than a transfer can be.
+    pub fn start<U>(&self, dma: &D, peripheral: u32, len: usize) -> Result<(), Error>
+    where
+        T: Unsize<[U]>,
+    {
+        if Stream::<D, N>::is_enabled(dma) || self.is_locked() {
+            return Err(Error::InUse);
         }
 
-        if dma1.lisr.read().teif2().bit_is_set() {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.lisr.read().tcif2().bit_is_set() {
-            unsafe { self.unlock(state) }
-            dma1.lifcr.write(|w| w.ctcif2().set_bit());
-            dma1.s2cr.modify(|_, w| w.en().clear_bit());
-            Ok(())
-        } else {
-            Err(nb::Error::WouldBlock)
+        let capacity = {
+            let slice: &[U] = unsafe { &*self.data.get() };
+            slice.len()
+        };
+        if len == 0 || len > capacity || len > 0xffff {
+            return Err(Error::Length);
         }
-    }
-}
 
-impl<T> Buffer<T, Dma1Stream4> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &DMA1) -> nb::Result<(), Error> {
-        let state = self.state.get();
-
-        if state == State::Unlocked {
-            return Ok(());
-        }
+        let buffer: &[U] = self.lock();
+        Stream::<D, N>::start(dma, peripheral, buffer.as_ptr() as u32, len as u16);
 
-        if dma1.hisr.read().teif4().bit_is_set() {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.hisr.read().tcif4().bit_is_set() {
-            unsafe { self.unlock(state) }
-            dma1.hifcr.write(|w| w.ctcif4().set_bit());
-            dma1.s2cr.modify(|_, w| w.en().clear_bit());
-            Ok(())
-        } else {
-            Err(nb::Error::WouldBlock)
-        }
+        Ok(())
     }
-}
 
-impl<T> Buffer<T, Dma1Stream5> {
     /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &DMA1) -> nb::Result<(), Error> {
+    pub fn release(&self, dma: &D) -> nb::Result<(), Error> {
         let state = self.state.get();
 
         if state == State::Unlocked {
             return Ok(());
         }
 
-        if dma1.hisr.read().teif5().bit_is_set() {
+        let flags = Stream::<D, N>::flags(dma);
+        if flags.transfer_error {
             Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.hisr.read().tcif5().bit_is_set() {
+        } else if flags.transfer_complete {
             unsafe { self.unlock(state) }
-            dma1.hifcr.write(|w| w.ctcif5().set_bit());
-            dma1.s2cr.modify(|_, w| w.en().clear_bit());
+            Stream::<D, N>::clear_flags(dma, Flags::transfer_complete());
+            Stream::<D, N>::disable(dma);
             Ok(())
         } else {
             Err(nb::Error::WouldBlock)
@@ -286,94 +714,129 @@ impl<T> Buffer<T, Dma1Stream5> {
     }
 }
 
-impl<T> Buffer<T, Dma1Stream6> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &DMA1) -> nb::Result<(), Error> {
-        let state = self.state.get();
+/// Two independent buffers used by a `STREAM` in double buffer mode
+///
+/// Unlike `CircBuffer` the buffers don't need to be contiguous. While the DMA
+/// accesses one of them the other one is idle and can be replaced with a
+/// fresh buffer with `swap`, which hands back the one the DMA is done with.
+pub struct DoubleBuffer<B: 'static, STREAM> {
+    _marker: PhantomData<STREAM>,
+    buffers: [&'static mut B; 2],
+    /// Buffer the DMA is expected to be accessing
+    target: usize,
+}
 
-        if state == State::Unlocked {
-            return Ok(());
+impl<B, D, N> DoubleBuffer<B, Stream<D, N>>
+where
+    D: Controller,
+    N: StreamNumber,
+{
+    /// Starts a transfer between the peripheral register at `peripheral` and
+    /// `buffers`, beginning with `buffers[0]`
+    ///
+    /// `config.msize` must match the size of `T`. The circular bit is implied
+    /// by double buffer mode and memory to memory transfers are not
+    /// supported. Returns `InUse` while the stream is enabled and `Length` if
+    /// the buffers are empty or longer than a transfer can be.
+    pub fn start<T>(
+        dma: &D,
+        config: &Config,
+        peripheral: u32,
+        buffers: [&'static mut B; 2],
+    ) -> Result<Self, Error>
+    where
+        B: Unsize<[T]>,
+    {
+        if Stream::<D, N>::is_enabled(dma) {
+            return Err(Error::InUse);
         }
 
-        if dma1.hisr.read().teif6().bit_is_set() {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.hisr.read().tcif6().bit_is_set() {
-            unsafe { self.unlock(state) }
-            dma1.hifcr.write(|w| w.ctcif6().set_bit());
-            dma1.s2cr.modify(|_, w| w.en().clear_bit());
-            Ok(())
-        } else {
-            Err(nb::Error::WouldBlock)
+        assert_ne!(
+            config.direction,
+            Direction::MemoryToMemory,
+            "double buffer mode can't be used in memory to memory transfers"
+        );
+        assert_eq!(
+            mem::size_of::<T>(),
+            1 << (config.msize as usize),
+            "memory size doesn't match the buffer"
+        );
+
+        let len = {
+            let slice: &[T] = &*buffers[0];
+            slice.len()
+        };
+        if len == 0 || len > 0xffff {
+            return Err(Error::Length);
         }
-    }
-}
-
-impl<T> Buffer<T, Dma2Stream0> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma2: &DMA2) -> nb::Result<(), Error> {
-        let state = self.state.get();
 
-        if state == State::Unlocked {
-            return Ok(());
-        }
+        let config = Config {
+            circular: true,
+            ..*config
+        };
+        Stream::<D, N>::configure(dma, &config);
+        Stream::<D, N>::set_double_buffer(dma, true);
+        Stream::<D, N>::set_memory1_address(dma, &*buffers[1] as *const B as u32);
+        Stream::<D, N>::start(dma, peripheral, &*buffers[0] as *const B as u32, len as u16);
 
-        if dma2.lisr.read().teif0().bit_is_set() {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma2.lisr.read().tcif0().bit_is_set() {
-            unsafe { self.unlock(state) }
-            dma2.lifcr.write(|w| w.ctcif0().set_bit());
-            dma2.s2cr.modify(|_, w| w.en().clear_bit());
-            Ok(())
-        } else {
-            Err(nb::Error::WouldBlock)
-        }
+        Ok(DoubleBuffer {
+            _marker: PhantomData,
+            buffers: buffers,
+            target: 0,
+        })
     }
-}
-/*
-impl<T> Buffer<T, Dma2Stream1> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma2: &DMA2) -> nb::Result<(), Error> {
-        let state = self.state.get();
 
-        if state == State::Unlocked {
-            return Ok(());
-        }
+    /// Waits until the DMA is done with the current buffer
+    ///
+    /// Returns `Error::Overrun` if the DMA got done with both buffers since
+    /// the last `swap`, i.e. it's accessing a buffer software still owns.
+    pub fn wait(&self, dma: &D) -> nb::Result<(), Error> {
+        let flags = Stream::<D, N>::flags(dma);
 
-        if dma2.lisr.read().teif1().bit_is_set() {
+        if flags.transfer_error {
             Err(nb::Error::Other(Error::Transfer))
-        } else if dma2.lisr.read().tcif1().bit_is_set() {
-            unsafe { self.unlock(state) }
-            dma2.lifcr.write(|w| w.ctcif1().set_bit());
-            dma2.s2cr.modify(|_, w| w.en().clear_bit());
-            Ok(())
+        } else if flags.transfer_complete {
+            if Stream::<D, N>::current_target(dma) == self.target {
+                Err(nb::Error::Other(Error::Overrun))
+            } else {
+                Ok(())
+            }
         } else {
             Err(nb::Error::WouldBlock)
         }
     }
-}
 
-impl<T> Buffer<T, Dma2Stream6> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma2: &DMA2) -> nb::Result<(), Error> {
-        let state = self.state.get();
+    /// Replaces the buffer the DMA is done with by `fresh` and returns it
+    ///
+    /// # Panics
+    ///
+    /// If `wait` wouldn't return `Ok`
+    pub fn swap(&mut self, dma: &D, fresh: &'static mut B) -> &'static mut B {
+        assert!(self.wait(dma).is_ok(), "no buffer is idle");
 
-        if state == State::Unlocked {
-            return Ok(());
-        }
+        Stream::<D, N>::clear_flags(dma, Flags::transfer_complete());
 
-        if dma2.lisr.read().teif6().bit_is_set() {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma2.lisr.read().tcif6().bit_is_set() {
-            unsafe { self.unlock(state) }
-            dma2.lifcr.write(|w| w.ctcif6().set_bit());
-            dma2.s2cr.modify(|_, w| w.en().clear_bit());
-            Ok(())
+        let idle = self.target;
+        let address = &*fresh as *const B as u32;
+        if idle == 0 {
+            Stream::<D, N>::set_memory_address(dma, address);
         } else {
-            Err(nb::Error::WouldBlock)
+            Stream::<D, N>::set_memory1_address(dma, address);
         }
+        self.target = 1 - idle;
+
+        mem::replace(&mut self.buffers[idle], fresh)
+    }
+
+    /// Stops the transfer and returns both buffers
+    pub fn stop(self, dma: &D) -> [&'static mut B; 2] {
+        Stream::<D, N>::disable(dma);
+        Stream::<D, N>::set_double_buffer(dma, false);
+        Stream::<D, N>::clear_flags(dma, Flags::all());
+
+        self.buffers
     }
 }
-*/
 
 /// A circular buffer associated to a DMA `STREAM`
 pub struct CircBuffer<B, STREAM> {
@@ -413,10 +876,63 @@ impl<B, STREAM> CircBuffer<B, STREAM> {
     }
 }
 
-impl<B> CircBuffer<B, Dma1Stream1> {
+impl<B, D, N> CircBuffer<B, Stream<D, N>>
+where
+    D: Controller,
+    N: StreamNumber,
+{
+    /// Starts a circular transfer between the register at `peripheral` and
+    /// both halves of the buffer
+    ///
+    /// `config.msize` must match the size of `T`, the circular bit is
+    /// implied. Returns `InUse` while the stream is enabled and `Length` if
+    /// the buffer is empty or longer than a transfer can be.
+    pub fn start<T>(&self, dma: &D, config: &Config, peripheral: u32) -> Result<(), Error>
+    where
+        B: Unsize<[T]>,
+    {
+        if Stream::<D, N>::is_enabled(dma) {
+            return Err(Error::InUse);
+        }
+
+        assert_eq!(
+            mem::size_of::<T>(),
+            1 << (config.msize as usize),
+            "memory size doesn't match the buffer"
+        );
+
+        let len = {
+            let half: &[T] = unsafe { &(*self.buffer.get())[0] };
+            half.len() * 2
+        };
+        if len == 0 || len > 0xffff {
+            return Err(Error::Length);
+        }
+
+        let buffer: &[T] = &self.lock()[0];
+        Stream::<D, N>::configure(
+            dma,
+            &Config {
+                circular: true,
+                ..*config
+            },
+        );
+        Stream::<D, N>::start(dma, peripheral, buffer.as_ptr() as u32, len as u16);
+
+        Ok(())
+    }
+
+    /// Stops the circular transfer and frees the buffer, so it can be used
+    /// by a new transfer
+    pub fn stop(&self, dma: &D) {
+        Stream::<D, N>::disable(dma);
+        Stream::<D, N>::clear_flags(dma, Flags::all());
+        self.state.set(CircState::Free);
+    }
+
     /// Yields read access to the half of the circular buffer that's not
     /// currently being mutated by the DMA
-    pub fn read<R, F>(&self, dma1: &DMA1, f: F) -> nb::Result<R, Error>
+    pub fn read<R, F>(&self, dma: &D, f: F) -> nb::Result<R, Error>
     where
         F: FnOnce(&B) -> R,
     {
@@ -424,22 +940,22 @@ impl<B> CircBuffer<B, Dma1Stream1> {
 
         assert_ne!(state, CircState::Free);
 
-        let isr = dma1.lisr.read();
+        let flags = Stream::<D, N>::flags(dma);
 
-        if isr.teif1().bit_is_set() {
+        if flags.transfer_error {
             Err(nb::Error::Other(Error::Transfer))
         } else {
             match state {
-                CircState::MutatingFirstHalf => if isr.tcif1().bit_is_set() {
+                CircState::MutatingFirstHalf => if flags.transfer_complete {
                     Err(nb::Error::Other(Error::Overrun))
-                } else if isr.htif1().bit_is_set() {
-                    dma1.lifcr.write(|w| w.chtif1().set_bit());
+                } else if flags.half_transfer {
+                    Stream::<D, N>::clear_flags(dma, Flags::half_transfer());
 
                     self.state.set(CircState::MutatingSecondHalf);
 
                     let ret = f(unsafe { &(*self.buffer.get())[0] });
 
-                    if isr.tcif1().bit_is_set() {
+                    if Stream::<D, N>::flags(dma).transfer_complete {
                         Err(nb::Error::Other(Error::Overrun))
                     } else {
                         Ok(ret)
@@ -447,16 +963,16 @@ impl<B> CircBuffer<B, Dma1Stream1> {
                 } else {
                     Err(nb::Error::WouldBlock)
                 },
-                CircState::MutatingSecondHalf => if isr.htif1().bit_is_set() {
+                CircState::MutatingSecondHalf => if flags.half_transfer {
                     Err(nb::Error::Other(Error::Overrun))
-                } else if isr.tcif1().bit_is_set() {
-                    dma1.lifcr.write(|w| w.ctcif1().set_bit());
+                } else if flags.transfer_complete {
+                    Stream::<D, N>::clear_flags(dma, Flags::transfer_complete());
 
                     self.state.set(CircState::MutatingFirstHalf);
 
                     let ret = f(unsafe { &(*self.buffer.get())[1] });
 
-                    if isr.htif1().bit_is_set() {
+                    if Stream::<D, N>::flags(dma).half_transfer {
                         Err(nb::Error::Other(Error::Overrun))
                     } else {
                         Ok(ret)
@@ -470,118 +986,315 @@ impl<B> CircBuffer<B, Dma1Stream1> {
     }
 }
 
-impl<B> CircBuffer<B, Dma2Stream0> {
-    /// Yields read access to the half of the circular buffer that's not
-    /// currently being mutated by the DMA
-    pub fn read<R, F>(&self, dma2: &DMA2, f: F) -> nb::Result<R, Error>
-    where
-        F: FnOnce(&B) -> R,
-    {
-        let state = self.state.get();
+/// Memory to memory transfer configuration
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryConfig {
+    /// Data size of each transfer, both addresses and the length must be
+    /// multiples of it
+    pub width: Size,
+    /// Burst size, `width` times the number of beats must fit the 16-byte
+    /// FIFO
+    pub burst: Burst,
+    /// Priority
+    pub priority: Priority,
+    /// Enables the transfer complete and transfer error interrupts
+    pub interrupt: bool,
+}
 
-        assert_ne!(state, CircState::Free);
+impl Default for MemoryConfig {
+    /// Single word transfers, medium priority, no interrupt
+    fn default() -> Self {
+        MemoryConfig {
+            width: Size::Word,
+            burst: Burst::Single,
+            priority: Priority::Medium,
+            interrupt: false,
+        }
+    }
+}
 
-        let isr = dma2.lisr.read();
+/// A memory to memory transfer on a DMA2 `STREAM`
+///
+/// The CPU is free while the transfer runs, use `wait` to poll for
+/// completion, or to check it from the stream's interrupt handler.
+///
+/// Transfers of more than 65535 items are split into chunks, `wait` starts
+/// the next chunk when the previous one completes.
+pub struct MemoryTransfer<T: 'static, STREAM> {
+    _marker: PhantomData<STREAM>,
+    destination: &'static mut [T],
+    source: usize,
+    pinc: bool,
+    width: usize,
+    /// Length of the transfer, in bytes
+    len: usize,
+    /// Largest chunk, in bytes
+    chunk: usize,
+    /// Bytes handed to the DMA so far
+    offset: Cell<usize>,
+}
 
-        if isr.teif0().bit_is_set() {
-            Err(nb::Error::Other(Error::Transfer))
-        } else {
-            match state {
-                CircState::MutatingFirstHalf => if isr.tcif0().bit_is_set() {
-                    Err(nb::Error::Other(Error::Overrun))
-                } else if isr.htif0().bit_is_set() {
-                    dma2.lifcr.write(|w| w.chtif0().set_bit());
+impl<T, N> MemoryTransfer<T, Stream<DMA2, N>>
+where
+    N: StreamNumber,
+{
+    /// Starts copying `source` into `destination`, both of the same length
+    pub fn copy(
+        dma: &DMA2,
+        rcc: &RCC,
+        source: &'static [T],
+        destination: &'static mut [T],
+        config: &MemoryConfig,
+    ) -> Result<Self, Error> {
+        if source.len() != destination.len() {
+            return Err(Error::Length);
+        }
 
-                    self.state.set(CircState::MutatingSecondHalf);
+        let bytes = source.len() * mem::size_of::<T>();
+        let align = (1 << config.width as usize) * config.burst.beats();
+        if source.as_ptr() as usize % align != 0 {
+            return Err(Error::Misaligned);
+        }
 
-                    let ret = f(unsafe { &(*self.buffer.get())[0] });
+        Self::start(dma, rcc, source.as_ptr() as usize, bytes, true, destination, config)
+    }
 
-                    if isr.tcif0().bit_is_set() {
-                        Err(nb::Error::Other(Error::Overrun))
-                    } else {
-                        Ok(ret)
-                    }
-                } else {
-                    Err(nb::Error::WouldBlock)
-                },
-                CircState::MutatingSecondHalf => if isr.htif0().bit_is_set() {
-                    Err(nb::Error::Other(Error::Overrun))
-                } else if isr.tcif0().bit_is_set() {
-                    dma2.lifcr.write(|w| w.ctcif0().set_bit());
+    /// Starts filling `destination` with copies of `value`, whose size must
+    /// be the transfer width
+    pub fn fill(
+        dma: &DMA2,
+        rcc: &RCC,
+        value: &'static T,
+        destination: &'static mut [T],
+        config: &MemoryConfig,
+    ) -> Result<Self, Error> {
+        let width = 1 << config.width as usize;
+        if mem::size_of::<T>() != width || value as *const T as usize % width != 0 {
+            return Err(Error::Misaligned);
+        }
 
-                    self.state.set(CircState::MutatingFirstHalf);
+        Self::start(dma, rcc, value as *const T as usize, width, false, destination, config)
+    }
 
-                    let ret = f(unsafe { &(*self.buffer.get())[1] });
+    /// Programs the stream and starts the first chunk, `source_len` is the
+    /// number of bytes read from `source`
+    fn start(
+        dma: &DMA2,
+        rcc: &RCC,
+        source: usize,
+        source_len: usize,
+        pinc: bool,
+        destination: &'static mut [T],
+        config: &MemoryConfig,
+    ) -> Result<Self, Error> {
+        rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+
+        if Stream::<DMA2, N>::is_enabled(dma) {
+            return Err(Error::InUse);
+        }
 
-                    if isr.htif0().bit_is_set() {
-                        Err(nb::Error::Other(Error::Overrun))
-                    } else {
-                        Ok(ret)
-                    }
-                } else {
-                    Err(nb::Error::WouldBlock)
-                },
-                _ => unreachable!(),
-            }
+        let width = 1 << config.width as usize;
+        let beats = config.burst.beats();
+        assert!(width * beats <= 16, "burst doesn't fit the FIFO");
+
+        // RM0090 10.3.11
+        // Bursts must not cross a 1 KB boundary, aligning the destination and
+        // the length to the burst size guarantees it
+        let address = destination.as_ptr() as usize;
+        let bytes = destination.len() * mem::size_of::<T>();
+        let align = width * beats;
+        if address % align != 0 || bytes % align != 0 {
+            return Err(Error::Misaligned);
+        }
+
+        if bytes == 0 {
+            return Err(Error::Length);
+        }
+
+        if source < address + bytes && address < source + source_len {
+            return Err(Error::Overlap);
         }
+
+        let interrupts = Flags {
+            transfer_complete: config.interrupt,
+            transfer_error: config.interrupt,
+            ..Flags::default()
+        };
+        Stream::<DMA2, N>::configure(
+            dma,
+            &Config {
+                channel: DmaChannel::_0,
+                priority: config.priority,
+                direction: Direction::MemoryToMemory,
+                msize: config.width,
+                psize: config.width,
+                minc: true,
+                pinc: pinc,
+                circular: false,
+                interrupts: interrupts,
+            },
+        );
+
+        // PBURST[22:21], MBURST[24:23]
+        let burst = config.burst as u32;
+        Stream::<DMA2, N>::modify(dma, CR, |cr| cr | (burst << 21) | (burst << 23));
+
+        // RM0090 10.5.10
+        // DMDIS[2]: Direct mode disabled, required in memory to memory mode
+        // FTH[1:0]: Full FIFO threshold
+        Stream::<DMA2, N>::write(dma, FCR, (1 << 2) | 0b11);
+
+        // NDTR is 16 bits, chunks stay a multiple of the burst
+        let items = 0xffff - 0xffff % beats;
+
+        let transfer = MemoryTransfer {
+            _marker: PhantomData,
+            destination: destination,
+            source: source,
+            pinc: pinc,
+            width: width,
+            len: bytes,
+            chunk: items * width,
+            offset: Cell::new(0),
+        };
+        transfer.next_chunk(dma);
+
+        Ok(transfer)
     }
-}
-/*
-//FIXME!
-impl<B> CircBuffer<B, Dma2Stream2> {
-    /// Yields read access to the half of the circular buffer that's not
-    /// currently being mutated by the DMA
-    pub fn read<R, F>(&self, dma2: &DMA2, f: F) -> nb::Result<R, Error>
-    where
-        F: FnOnce(&B) -> R,
-    {
-        let state = self.state.get();
 
-        assert_ne!(state, CircState::Free);
+    /// Programs and enables the stream for the next chunk
+    fn next_chunk(&self, dma: &DMA2) {
+        let offset = self.offset.get();
+        let bytes = cmp::min(self.len - offset, self.chunk);
+        let source = if self.pinc {
+            self.source + offset
+        } else {
+            self.source
+        };
 
-        let isr = dma2.lisr.read();
+        Stream::<DMA2, N>::start(
+            dma,
+            source as u32,
+            (self.destination.as_ptr() as usize + offset) as u32,
+            (bytes / self.width) as u16,
+        );
 
-        if isr.teif0().bit_is_set() {
+        self.offset.set(offset + bytes);
+    }
+
+    /// Waits until the transfer is complete
+    ///
+    /// Clears the transfer complete flag once the last chunk is done, so the
+    /// stream's interrupt doesn't keep firing until `release`.
+    pub fn wait(&self, dma: &DMA2) -> nb::Result<(), Error> {
+        let flags = Stream::<DMA2, N>::flags(dma);
+
+        if flags.transfer_error {
             Err(nb::Error::Other(Error::Transfer))
+        } else if flags.transfer_complete {
+            if self.offset.get() < self.len {
+                // `enable` clears the flags
+                self.next_chunk(dma);
+                Err(nb::Error::WouldBlock)
+            } else {
+                Stream::<DMA2, N>::clear_flags(dma, Flags::transfer_complete());
+                Ok(())
+            }
+        } else if self.offset.get() == self.len && !Stream::<DMA2, N>::is_enabled(dma) {
+            // already reported, the flag has been cleared
+            Ok(())
         } else {
-            match state {
-                CircState::MutatingFirstHalf => if isr.tcif0().bit_is_set() {
-                    Err(nb::Error::Other(Error::Overrun))
-                } else if isr.htif0().bit_is_set() {
-                    dma2.lifcr.write(|w| w.chtif0().set_bit());
-
-                    self.state.set(CircState::MutatingSecondHalf);
+            Err(nb::Error::WouldBlock)
+        }
+    }
 
-                    let ret = f(unsafe { &(*self.buffer.get())[0] });
+    /// Returns the destination, aborting the transfer if it's not complete
+    pub fn release(self, dma: &DMA2) -> &'static mut [T] {
+        Stream::<DMA2, N>::disable(dma);
+        Stream::<DMA2, N>::clear_flags(dma, Flags::all());
 
-                    if isr.tcif0().bit_is_set() {
-                        Err(nb::Error::Other(Error::Overrun))
-                    } else {
-                        Ok(ret)
-                    }
-                } else {
-                    Err(nb::Error::WouldBlock)
-                },
-                CircState::MutatingSecondHalf => if isr.htif0().bit_is_set() {
-                    Err(nb::Error::Other(Error::Overrun))
-                } else if isr.tcif0().bit_is_set() {
-                    dma2.lifcr.write(|w| w.ctcif0().set_bit());
+        self.destination
+    }
+}
 
-                    self.state.set(CircState::MutatingFirstHalf);
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use stm32f40x::DMA1;
+
+    use super::*;
+
+    macro_rules! check_stream {
+        ($dma:ident, $S:ident, $n:expr, $cr:ident, $ndtr:ident, $par:ident, $m0ar:ident,
+         $m1ar:ident, $fcr:ident, $isr:ident, $ifcr:ident, $shift:expr) => {{
+            let base = &$dma as *const DMA1 as usize;
+            let address = |offset| register(&$dma, offset) as usize;
+
+            assert_eq!($S::number(), $n);
+            assert_eq!(address(Stream::<DMA1, $S>::offset(CR)), &$dma.$cr as *const _ as usize);
+            assert_eq!(
+                address(Stream::<DMA1, $S>::offset(NDTR)),
+                &$dma.$ndtr as *const _ as usize
+            );
+            assert_eq!(
+                address(Stream::<DMA1, $S>::offset(PAR)),
+                &$dma.$par as *const _ as usize
+            );
+            assert_eq!(
+                address(Stream::<DMA1, $S>::offset(M0AR)),
+                &$dma.$m0ar as *const _ as usize
+            );
+            assert_eq!(
+                address(Stream::<DMA1, $S>::offset(M1AR)),
+                &$dma.$m1ar as *const _ as usize
+            );
+            assert_eq!(
+                address(Stream::<DMA1, $S>::offset(FCR)),
+                &$dma.$fcr as *const _ as usize
+            );
+
+            let (isr, shift) = Stream::<DMA1, $S>::flag_offset(ISR);
+            assert_eq!(base + isr, &$dma.$isr as *const _ as usize);
+            assert_eq!(shift, $shift);
+            let (ifcr, _) = Stream::<DMA1, $S>::flag_offset(IFCR);
+            assert_eq!(base + ifcr, &$dma.$ifcr as *const _ as usize);
+        }}
+    }
 
-                    let ret = f(unsafe { &(*self.buffer.get())[1] });
+    #[test]
+    fn register_offsets() {
+        // only addresses are taken, no register is accessed
+        let dma1: DMA1 = unsafe { mem::zeroed() };
+
+        check_stream!(dma1, S0, 0, s0cr, s0ndtr, s0par, s0m0ar, s0m1ar, s0fcr,
+                      lisr, lifcr, 0);
+        check_stream!(dma1, S1, 1, s1cr, s1ndtr, s1par, s1m0ar, s1m1ar, s1fcr,
+                      lisr, lifcr, 6);
+        check_stream!(dma1, S2, 2, s2cr, s2ndtr, s2par, s2m0ar, s2m1ar, s2fcr,
+                      lisr, lifcr, 16);
+        check_stream!(dma1, S3, 3, s3cr, s3ndtr, s3par, s3m0ar, s3m1ar, s3fcr,
+                      lisr, lifcr, 22);
+        check_stream!(dma1, S4, 4, s4cr, s4ndtr, s4par, s4m0ar, s4m1ar, s4fcr,
+                      hisr, hifcr, 0);
+        check_stream!(dma1, S5, 5, s5cr, s5ndtr, s5par, s5m0ar, s5m1ar, s5fcr,
+                      hisr, hifcr, 6);
+        check_stream!(dma1, S6, 6, s6cr, s6ndtr, s6par, s6m0ar, s6m1ar, s6fcr,
+                      hisr, hifcr, 16);
+        check_stream!(dma1, S7, 7, s7cr, s7ndtr, s7par, s7m0ar, s7m1ar, s7fcr,
+                      hisr, hifcr, 22);
+    }
 
-                    if isr.htif0().bit_is_set() {
-                        Err(nb::Error::Other(Error::Overrun))
-                    } else {
-                        Ok(ret)
-                    }
-                } else {
-                    Err(nb::Error::WouldBlock)
-                },
-                _ => unreachable!(),
-            }
-        }
+    #[test]
+    fn flag_bits() {
+        let flags = Flags {
+            half_transfer: true,
+            fifo_error: true,
+            ..Flags::default()
+        };
+        // HTIFx[4], FEIFx[0]
+        assert_eq!(flags.bits(), 0b01_0001);
+        assert_eq!(Flags::from_bits(0b11_1101), Flags::all());
+        assert_eq!(Flags::from_bits(0b10_0000), Flags::transfer_complete());
     }
 }
-*/
@@ -0,0 +1,359 @@
+//! DShot ESC protocol output
+//!
+//! A DShot packet is 16 bits sent MSB first: an 11-bit value, a telemetry
+//! request bit and a 4-bit checksum. Every bit is one PWM period whose high
+//! time is 37.5% (zero) or 75% (one) of the bit period.
+//!
+//! The driver sends one packet to up to four motors at once. The duty cycles
+//! of all channels are interleaved in a `Buffer` and written by DMA1 stream 2
+//! (TIM3_UP request) into CCR1..CCRn using the DMA burst feature of TIM3.
+//!
+//! # TIM3
+//!
+//! - Motor 1 = CH1 = PA6
+//! - Motor 2 = CH2 = PC7
+//! - Motor 3 = CH3 = PB0
+//! - Motor 4 = CH4 = PB1
+//!
+//! The packet encoder doesn't touch any peripheral and can be checked on the
+//! host.
+
+use core::marker::Unsize;
+
+use cast::u16;
+use hal::prelude::*;
+use nb;
+use static_ref::Static;
+use stm32f40x::{DMA1, TIM3, GPIOA, GPIOB, GPIOC, RCC};
+
+use dma::{self, Buffer, Config, Direction, Dma1Stream2, DmaChannel, Flags, Priority, Size};
+use pwm::Pwm;
+use time::Hertz;
+use timer::Channel;
+
+/// Number of bits in a packet
+pub const PACKET_BITS: usize = 16;
+
+/// Number of zero duty periods sent after a packet so the line idles low
+pub const IDLE_SLOTS: usize = 2;
+
+/// Number of duty cycle slots per channel in a frame
+pub const FRAME_SLOTS: usize = PACKET_BITS + IDLE_SLOTS;
+
+/// Largest throttle value
+///
+/// Throttle 0 is sent as the motor stop command (value 0) and throttles
+/// `1 ..= MAX_THROTTLE` as values `48 ..= 2047`.
+pub const MAX_THROTTLE: u16 = 2000;
+
+/// Offset of CCR1 in the TIM3 register block, in words, for TIMx_DCR.DBA
+const CCR1_OFFSET: u8 = 0x34 / 4;
+
+/// Bit rate
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Speed {
+    /// 150 kbit/s
+    DShot150,
+    /// 300 kbit/s
+    DShot300,
+    /// 600 kbit/s
+    DShot600,
+}
+
+impl Speed {
+    /// Bit rate of this speed
+    pub fn bit_rate(&self) -> Hertz {
+        match *self {
+            Speed::DShot150 => Hertz(150_000),
+            Speed::DShot300 => Hertz(300_000),
+            Speed::DShot600 => Hertz(600_000),
+        }
+    }
+}
+
+/// Special commands, sent in place of a throttle value while the motors are
+/// stopped
+///
+/// Most commands must be repeated several times (e.g. 6 to 10) before the ESC
+/// acts on them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Stop the motor (same as throttle zero)
+    MotorStop = 0,
+    /// Beep tone 1
+    Beep1 = 1,
+    /// Beep tone 2
+    Beep2 = 2,
+    /// Beep tone 3
+    Beep3 = 3,
+    /// Beep tone 4
+    Beep4 = 4,
+    /// Beep tone 5
+    Beep5 = 5,
+    /// Request ESC information
+    EscInfo = 6,
+    /// Spin direction 1
+    SpinDirection1 = 7,
+    /// Spin direction 2
+    SpinDirection2 = 8,
+    /// Disable 3D mode
+    ThreeDModeOff = 9,
+    /// Enable 3D mode
+    ThreeDModeOn = 10,
+    /// Request the ESC settings
+    SettingsRequest = 11,
+    /// Save the settings
+    SaveSettings = 12,
+    /// Normal spin direction
+    SpinDirectionNormal = 20,
+    /// Reversed spin direction
+    SpinDirectionReversed = 21,
+}
+
+/// Builds a packet from an 11-bit `value` and the telemetry request bit
+pub fn packet(value: u16, telemetry: bool) -> u16 {
+    let data = ((value & 0x7ff) << 1) | telemetry as u16;
+    (data << 4) | checksum(data)
+}
+
+/// Checksum of the 12 data bits of a packet
+pub fn checksum(data: u16) -> u16 {
+    (data ^ (data >> 4) ^ (data >> 8)) & 0xf
+}
+
+/// Packet for `throttle`, in the range `0 ..= MAX_THROTTLE`
+///
+/// Throttle 0 stops the motor, values above `MAX_THROTTLE` are clamped.
+pub fn throttle_packet(throttle: u16, telemetry: bool) -> u16 {
+    let value = if throttle == 0 {
+        Command::MotorStop as u16
+    } else if throttle > MAX_THROTTLE {
+        MAX_THROTTLE + 47
+    } else {
+        throttle + 47
+    };
+    packet(value, telemetry)
+}
+
+/// Packet for the special `command`, commands always request telemetry
+pub fn command_packet(command: Command) -> u16 {
+    packet(command as u16, true)
+}
+
+/// Encodes one packet per channel into interleaved duty cycles
+///
+/// `out` receives `FRAME_SLOTS` groups of `packets.len()` duty cycles: for
+/// every bit, the duty cycle of channel 1, then channel 2 and so on. Returns
+/// the number of slots written.
+///
+/// # Panics
+///
+/// Panics if `out` is shorter than `FRAME_SLOTS * packets.len()`
+pub fn encode(packets: &[u16], t0h: u16, t1h: u16, out: &mut [u16]) -> usize {
+    let n = packets.len();
+    if n == 0 {
+        return 0;
+    }
+
+    let len = FRAME_SLOTS * n;
+    assert!(out.len() >= len, "frame buffer too small");
+
+    for (bit, slots) in out[..len].chunks_mut(n).enumerate() {
+        for (slot, packet) in slots.iter_mut().zip(packets) {
+            *slot = if bit >= PACKET_BITS {
+                0
+            } else if packet & (0x8000 >> bit) != 0 {
+                t1h
+            } else {
+                t0h
+            };
+        }
+    }
+
+    len
+}
+
+/// DShot output on channels 1 to `channels` of TIM3
+pub struct DShot<'a> {
+    pwm: Pwm<'a, TIM3>,
+    channels: usize,
+    t0h: u16,
+    t1h: u16,
+}
+
+impl<'a> DShot<'a> {
+    /// Creates a driver for `channels` motors, 1 to 4
+    pub fn new(tim3: &'a TIM3, channels: usize) -> Self {
+        assert!(channels >= 1 && channels <= 4, "invalid number of channels");
+
+        DShot {
+            pwm: Pwm(tim3),
+            channels: channels,
+            t0h: 0,
+            t1h: 0,
+        }
+    }
+
+    /// Initializes TIM3 at the `speed` bit rate and DMA1 stream 2 for burst
+    /// transfers into the compare registers
+    pub fn init(&mut self, speed: Speed, dma1: &DMA1, gpioa: &GPIOA, gpiob: &GPIOB, gpioc: &GPIOC, rcc: &RCC) {
+        const CHANNELS: [Channel; 4] = [Channel::_1, Channel::_2, Channel::_3, Channel::_4];
+
+        for c in &CHANNELS[..self.channels] {
+            self.pwm.init(speed.bit_rate().invert(), *c, Some(dma1), gpioa, gpiob, gpioc, rcc);
+            self.pwm.set_duty(*c, 0);
+            self.pwm.enable(*c);
+        }
+
+        let max = self.pwm.get_max_duty();
+        self.t0h = u16(max * 3 / 8).unwrap();
+        self.t1h = u16(max * 3 / 4).unwrap();
+
+        // Every update event writes `channels` half-words starting at CCR1
+        let tim3 = self.pwm.0;
+        tim3.dcr.write(|w| unsafe {
+            w.dba().bits(CCR1_OFFSET).dbl().bits(self.channels as u8 - 1)
+        });
+
+        // TIM3_CH4/UP: Channel 5 (RM0368 9.3.3 Table 27)
+        // One frame per `send`, written half-word by half-word into DMAR
+        Dma1Stream2::configure(
+            dma1,
+            &Config {
+                channel: DmaChannel::_5,
+                priority: Priority::High,
+                direction: Direction::MemoryToPeripheral,
+                msize: Size::HalfWord,
+                psize: Size::HalfWord,
+                minc: true,
+                pinc: false,
+                circular: false,
+                interrupts: Flags::transfer_complete(),
+            },
+        );
+    }
+
+    /// Sends one packet per channel, `packets[0]` goes to channel 1
+    ///
+    /// The packets are encoded into `buffer`, which stays locked until it's
+    /// `release`d after the transfer completes. Returns `InUse` until then.
+    pub fn send<B>(
+        &self,
+        dma1: &DMA1,
+        packets: &[u16],
+        buffer: &Static<Buffer<B, Dma1Stream2>>,
+    ) -> Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        assert_eq!(packets.len(), self.channels);
+
+        // the buffer can't be encoded while the DMA reads it
+        if Dma1Stream2::is_enabled(dma1) || buffer.is_locked() {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = {
+            let mut out = buffer.borrow_mut();
+            encode(packets, self.t0h, self.t1h, &mut *out)
+        };
+
+        buffer.start::<u16>(dma1, &self.pwm.0.dmar as *const _ as u32, len)
+    }
+
+    /// Waits until the DMA releases `buffer` after a burst
+    pub fn release<B>(
+        &self,
+        dma1: &DMA1,
+        buffer: &Static<Buffer<B, Dma1Stream2>>,
+    ) -> nb::Result<(), dma::Error> {
+        buffer.release(dma1)
+    }
+
+    /// Sends `throttle` values, in the range `0 ..= MAX_THROTTLE`, one per
+    /// channel
+    pub fn send_throttles<B>(
+        &self,
+        dma1: &DMA1,
+        throttles: &[u16],
+        telemetry: bool,
+        buffer: &Static<Buffer<B, Dma1Stream2>>,
+    ) -> Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        let mut packets = [0; 4];
+        for (p, t) in packets.iter_mut().zip(throttles) {
+            *p = throttle_packet(*t, telemetry);
+        }
+        self.send(dma1, &packets[..throttles.len()], buffer)
+    }
+
+    /// Sends the special `command` to all channels
+    pub fn send_command<B>(
+        &self,
+        dma1: &DMA1,
+        command: Command,
+        buffer: &Static<Buffer<B, Dma1Stream2>>,
+    ) -> Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        let packets = [command_packet(command); 4];
+        self.send(dma1, &packets[..self.channels], buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_of_data_bits() {
+        // 1046 without telemetry
+        assert_eq!(checksum(0x82c), 0x6);
+        // 1046 with telemetry
+        assert_eq!(checksum(0x82d), 0x7);
+    }
+
+    #[test]
+    fn packets() {
+        assert_eq!(packet(1046, false), 0x82c6);
+        assert_eq!(packet(1046, true), 0x82d7);
+        assert_eq!(packet(0, false), 0x0000);
+        // only 11 bits of value
+        assert_eq!(packet(0x800 | 1046, false), 0x82c6);
+        assert_eq!(command_packet(Command::Beep1), 0x0033);
+    }
+
+    #[test]
+    fn throttle_packets() {
+        // motor stop
+        assert_eq!(throttle_packet(0, false), 0x0000);
+        // value 48, the lowest throttle
+        assert_eq!(throttle_packet(1, false), 0x0606);
+        assert_eq!(throttle_packet(999, false), packet(1046, false));
+        // value 2047, the highest throttle
+        assert_eq!(throttle_packet(MAX_THROTTLE, false), 0xffee);
+        assert_eq!(throttle_packet(u16::max_value(), false), 0xffee);
+    }
+
+    #[test]
+    fn encoding() {
+        let mut out = [0xffff; FRAME_SLOTS * 2];
+        let len = encode(&[0x8001, 0x4000], 3, 6, &mut out);
+        assert_eq!(len, FRAME_SLOTS * 2);
+
+        // MSB first, channels interleaved
+        assert_eq!(&out[..4], &[6, 3, 3, 6]);
+        assert_eq!(&out[30..32], &[6, 3]);
+        assert_eq!(&out[32..], &[0; IDLE_SLOTS * 2]);
+    }
+
+    #[test]
+    fn encoding_no_packets() {
+        let mut out = [0xffff; 4];
+        assert_eq!(encode(&[], 3, 6, &mut out), 0);
+        assert_eq!(out, [0xffff; 4]);
+    }
+}
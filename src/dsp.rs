@@ -0,0 +1,696 @@
+//! Signal processing helpers for sample blocks
+//!
+//! Everything here works on slices and caller provided storage, without
+//! allocating, so it can be used directly inside the closure passed to
+//! `CircBuffer::read`:
+//!
+//! ``` ignore
+//! BUFFER.read(dma2, |half| {
+//!     let n = cic.decimate(half, &mut decimated);
+//!     let level = dsp::ac_rms(&half[..]);
+//! })
+//! ```
+//!
+//! - `MovingAverage`: boxcar low-pass over the last N samples
+//! - `Cic`: cascaded integrator-comb decimating low-pass
+//! - `DcBlocker`: first order DC removal
+//! - `mean`, `rms`, `ac_rms`, `peak` and `find_peaks`
+//! - `Window`, `fft` and `rfft`: radix-2 FFT with windowing
+//!
+//! The statistics accept any `Sample` type, which includes the raw ADC
+//! `u16` samples and the `i32` output of `MovingAverage` and `Cic`.
+
+use core::marker::Unsize;
+
+use m::Float as _0;
+
+use math_utils::{cos, sin, TWO_PI};
+
+/// Moving average over the last `N` samples, `N` being the length of the
+/// buffer `B`
+pub struct MovingAverage<B> {
+    buffer: B,
+    index: usize,
+    len: usize,
+    sum: i32,
+}
+
+impl<B> MovingAverage<B>
+where
+    B: Unsize<[i32]>,
+{
+    /// Creates a moving average that uses `buffer` as window
+    ///
+    /// # Panics
+    ///
+    /// If `buffer` is empty
+    pub fn new(buffer: B) -> Self {
+        {
+            let window: &[i32] = &buffer;
+            assert!(!window.is_empty(), "empty moving average window");
+        }
+
+        let mut average = MovingAverage {
+            buffer: buffer,
+            index: 0,
+            len: 0,
+            sum: 0,
+        };
+        average.reset();
+        average
+    }
+
+    /// Forgets all samples
+    pub fn reset(&mut self) {
+        let buffer: &mut [i32] = &mut self.buffer;
+        for x in buffer.iter_mut() {
+            *x = 0;
+        }
+        self.index = 0;
+        self.len = 0;
+        self.sum = 0;
+    }
+
+    /// Adds a sample and returns the average of the window
+    ///
+    /// Until the window is full the average is taken over the samples seen so
+    /// far.
+    pub fn update(&mut self, x: i32) -> i32 {
+        let buffer: &mut [i32] = &mut self.buffer;
+        let n = buffer.len();
+
+        self.sum += x - buffer[self.index];
+        buffer[self.index] = x;
+        self.index = (self.index + 1) % n;
+        if self.len < n {
+            self.len += 1;
+        }
+
+        self.sum / self.len as i32
+    }
+
+    /// Filters a block, `output` must be at least as long as `input`
+    pub fn filter(&mut self, input: &[u16], output: &mut [i32]) {
+        assert!(output.len() >= input.len());
+
+        for (x, y) in input.iter().zip(output.iter_mut()) {
+            *y = self.update(i32::from(*x));
+        }
+    }
+}
+
+/// Highest supported CIC order
+pub const CIC_MAX_ORDER: usize = 4;
+
+/// Cascaded integrator-comb decimator
+///
+/// `order` integrators run at the input rate, followed by `order` combs
+/// (differential delay 1) at the output rate, one output every `rate`
+/// inputs. The output is divided by the filter gain `rate ^ order`, so it has
+/// the units of the input.
+pub struct Cic {
+    order: usize,
+    rate: u32,
+    gain: i32,
+    phase: u32,
+    integrators: [i32; CIC_MAX_ORDER],
+    combs: [i32; CIC_MAX_ORDER],
+}
+
+impl Cic {
+    /// Creates a decimator
+    ///
+    /// The gain `rate ^ order` must not exceed 2 ^ 19 so that 12-bit samples
+    /// can't overflow the 32-bit accumulators.
+    pub fn new(order: usize, rate: u32) -> Self {
+        assert!(order >= 1 && order <= CIC_MAX_ORDER, "invalid CIC order");
+        assert!(rate >= 1, "invalid decimation rate");
+
+        let mut gain: u32 = 1;
+        for _ in 0..order {
+            gain = gain.saturating_mul(rate);
+        }
+        assert!(gain <= 1 << 19, "CIC gain too large");
+
+        Cic {
+            order: order,
+            rate: rate,
+            gain: gain as i32,
+            phase: 0,
+            integrators: [0; CIC_MAX_ORDER],
+            combs: [0; CIC_MAX_ORDER],
+        }
+    }
+
+    /// Returns the gain `rate ^ order` removed from the output
+    pub fn gain(&self) -> i32 {
+        self.gain
+    }
+
+    /// Forgets the filter state
+    pub fn reset(&mut self) {
+        self.phase = 0;
+        self.integrators = [0; CIC_MAX_ORDER];
+        self.combs = [0; CIC_MAX_ORDER];
+    }
+
+    /// Adds a sample, returns an output every `rate` samples
+    pub fn update(&mut self, x: i32) -> Option<i32> {
+        // the integrators wrap around, the combs undo the wrap
+        let mut acc = x;
+        for integrator in self.integrators[..self.order].iter_mut() {
+            *integrator = integrator.wrapping_add(acc);
+            acc = *integrator;
+        }
+
+        self.phase += 1;
+        if self.phase < self.rate {
+            return None;
+        }
+        self.phase = 0;
+
+        for comb in self.combs[..self.order].iter_mut() {
+            let y = acc.wrapping_sub(*comb);
+            *comb = acc;
+            acc = y;
+        }
+
+        Some(acc / self.gain)
+    }
+
+    /// Decimates a block, returns the number of outputs written
+    ///
+    /// `output` must hold at least `input.len() / rate + 1` samples.
+    pub fn decimate(&mut self, input: &[u16], output: &mut [i32]) -> usize {
+        let mut n = 0;
+        for x in input {
+            if let Some(y) = self.update(i32::from(*x)) {
+                output[n] = y;
+                n += 1;
+            }
+        }
+        n
+    }
+}
+
+/// DC removal, `y[n] = x[n] - x[n - 1] + alpha * y[n - 1]`
+///
+/// The cut-off frequency is about `(1 - alpha) * fs / (2 * PI)`.
+pub struct DcBlocker {
+    alpha: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl DcBlocker {
+    /// Creates a DC blocker, `alpha` is typically 0.99 - 0.999
+    pub fn new(alpha: f32) -> Self {
+        assert!(alpha > 0. && alpha < 1., "invalid DC blocker pole");
+
+        DcBlocker {
+            alpha: alpha,
+            x1: 0.,
+            y1: 0.,
+        }
+    }
+
+    /// Forgets the filter state
+    pub fn reset(&mut self) {
+        self.x1 = 0.;
+        self.y1 = 0.;
+    }
+
+    /// Filters one sample
+    pub fn update(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + self.alpha * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+
+    /// Filters a block, `output` must be at least as long as `input`
+    pub fn filter(&mut self, input: &[u16], output: &mut [f32]) {
+        assert!(output.len() >= input.len());
+
+        for (x, y) in input.iter().zip(output.iter_mut()) {
+            *y = self.update(f32::from(*x));
+        }
+    }
+}
+
+/// A sample type the statistics can work on
+pub trait Sample: Copy {
+    /// Converts the sample to `f32`
+    fn to_f32(self) -> f32;
+}
+
+macro_rules! sample {
+    ($($T:ty),+) => {
+        $(
+            impl Sample for $T {
+                fn to_f32(self) -> f32 {
+                    self as f32
+                }
+            }
+        )+
+    }
+}
+
+sample!(u8, u16, i8, i16, i32, f32);
+
+/// Mean of a block, 0 if it's empty
+pub fn mean<T>(x: &[T]) -> f32
+where
+    T: Sample,
+{
+    if x.is_empty() {
+        return 0.;
+    }
+
+    let sum: f32 = x.iter().fold(0., |sum, x| {
+        let x = x.to_f32();
+        sum + x
+    });
+    sum / x.len() as f32
+}
+
+/// Root mean square of a block, 0 if it's empty
+pub fn rms<T>(x: &[T]) -> f32
+where
+    T: Sample,
+{
+    if x.is_empty() {
+        return 0.;
+    }
+
+    let sum: f32 = x.iter().fold(0., |sum, x| {
+        let x = x.to_f32();
+        sum + x * x
+    });
+    (sum / x.len() as f32).sqrt()
+}
+
+/// Root mean square of a block with its mean removed, i.e. its standard
+/// deviation
+pub fn ac_rms<T>(x: &[T]) -> f32
+where
+    T: Sample,
+{
+    if x.is_empty() {
+        return 0.;
+    }
+
+    let mean = mean(x);
+    let sum: f32 = x.iter().fold(0., |sum, x| {
+        let x = x.to_f32();
+        let x = x - mean;
+        sum + x * x
+    });
+    (sum / x.len() as f32).sqrt()
+}
+
+/// A sample of a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Peak {
+    /// Index in the block
+    pub index: usize,
+    /// Value
+    pub value: f32,
+}
+
+/// Returns the sample with the largest magnitude
+pub fn peak<T>(x: &[T]) -> Option<Peak>
+where
+    T: Sample,
+{
+    let mut peak: Option<Peak> = None;
+
+    for (i, x) in x.iter().enumerate() {
+        let value = x.to_f32();
+        if peak.map(|p| value.abs() > p.value.abs()).unwrap_or(true) {
+            peak = Some(Peak {
+                index: i,
+                value: value,
+            });
+        }
+    }
+
+    peak
+}
+
+/// Finds the local maxima of a block that are at least `threshold`
+///
+/// Of two maxima closer than `min_distance` samples only the higher one is
+/// kept. The indices are written to `peaks` in ascending order; returns how
+/// many were written, at most `peaks.len()`.
+pub fn find_peaks<T>(x: &[T], threshold: f32, min_distance: usize, peaks: &mut [usize]) -> usize
+where
+    T: Sample,
+{
+    let mut n = 0;
+
+    if x.len() < 3 || peaks.is_empty() {
+        return 0;
+    }
+
+    for i in 1..x.len() - 1 {
+        let value = x[i].to_f32();
+        let before = x[i - 1].to_f32();
+        let after = x[i + 1].to_f32();
+        if value < threshold || value <= before || value < after {
+            continue;
+        }
+
+        if n > 0 && i - peaks[n - 1] < min_distance {
+            let last = x[peaks[n - 1]].to_f32();
+            if value > last {
+                peaks[n - 1] = i;
+            }
+        } else if n < peaks.len() {
+            peaks[n] = i;
+            n += 1;
+        } else {
+            break;
+        }
+    }
+
+    n
+}
+
+/// Window applied before an FFT
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Window {
+    /// No windowing
+    Rectangular,
+    /// Hann window
+    Hann,
+    /// Hamming window
+    Hamming,
+    /// Blackman window
+    Blackman,
+}
+
+impl Window {
+    /// Returns the `i`-th coefficient of an `n` samples window
+    pub fn coefficient(&self, i: usize, n: usize) -> f32 {
+        let x = TWO_PI * i as f32 / n as f32;
+
+        match *self {
+            Window::Rectangular => 1.,
+            Window::Hann => 0.5 - 0.5 * cos(x),
+            Window::Hamming => 0.54 - 0.46 * cos(x),
+            Window::Blackman => 0.42 - 0.5 * cos(x) + 0.08 * cos(2. * x),
+        }
+    }
+
+    /// Multiplies a block by the window
+    pub fn apply(&self, x: &mut [f32]) {
+        let n = x.len();
+        if *self == Window::Rectangular {
+            return;
+        }
+
+        for (i, x) in x.iter_mut().enumerate() {
+            *x *= self.coefficient(i, n);
+        }
+    }
+}
+
+/// In-place radix-2 complex FFT
+///
+/// `re` and `im` hold the real and imaginary parts of the input and are
+/// replaced by the spectrum. Their length must be the same power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    assert_eq!(n, im.len());
+    assert!(n.is_power_of_two(), "FFT length must be a power of two");
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    // butterflies
+    let mut size = 2;
+    while size <= n {
+        let half = size / 2;
+        let angle = -TWO_PI / size as f32;
+        let (w_re, w_im) = (cos(angle), sin(angle));
+
+        let mut start = 0;
+        while start < n {
+            let (mut t_re, mut t_im) = (1., 0.);
+
+            for k in start..start + half {
+                let l = k + half;
+                let x_re = re[l] * t_re - im[l] * t_im;
+                let x_im = re[l] * t_im + im[l] * t_re;
+
+                re[l] = re[k] - x_re;
+                im[l] = im[k] - x_im;
+                re[k] += x_re;
+                im[k] += x_im;
+
+                let next = t_re * w_re - t_im * w_im;
+                t_im = t_re * w_im + t_im * w_re;
+                t_re = next;
+            }
+
+            start += size;
+        }
+
+        size *= 2;
+    }
+}
+
+/// FFT of a real block
+///
+/// `x` holds `n` samples, `n` a power of two. On return `re` and `im`, which
+/// must hold `n / 2 + 1` bins, contain the non-redundant half of the
+/// spectrum, bins `0 ..= n / 2`.
+///
+/// The samples are packed into an `n / 2` point complex FFT, even samples as
+/// the real parts and odd samples as the imaginary parts, whose result is
+/// then split into the spectrum of `x`.
+pub fn rfft(x: &[f32], re: &mut [f32], im: &mut [f32]) {
+    let n = x.len();
+    assert!(n >= 2 && n.is_power_of_two(), "FFT length must be a power of two");
+
+    let m = n / 2;
+    assert_eq!(re.len(), m + 1);
+    assert_eq!(im.len(), m + 1);
+
+    for k in 0..m {
+        re[k] = x[2 * k];
+        im[k] = x[2 * k + 1];
+    }
+
+    fft(&mut re[..m], &mut im[..m]);
+
+    // Z[k] being the packed FFT and W = e^(-2 * PI * i / n)
+    // E[k] = (Z[k] + conj(Z[m - k])) / 2, the FFT of the even samples
+    // O[k] = (Z[k] - conj(Z[m - k])) / 2i, the FFT of the odd samples
+    // X[k] = E[k] + W^k * O[k] and X[m - k] = conj(E[k] - W^k * O[k])
+    let (z_re, z_im) = (re[0], im[0]);
+    re[0] = z_re + z_im;
+    im[0] = 0.;
+    re[m] = z_re - z_im;
+    im[m] = 0.;
+
+    for k in 1..m / 2 + 1 {
+        let j = m - k;
+        let (a_re, a_im) = (re[k], im[k]);
+        let (b_re, b_im) = (re[j], im[j]);
+
+        let e_re = 0.5 * (a_re + b_re);
+        let e_im = 0.5 * (a_im - b_im);
+        let o_re = 0.5 * (a_im + b_im);
+        let o_im = -0.5 * (a_re - b_re);
+
+        let angle = -TWO_PI * k as f32 / n as f32;
+        let (w_re, w_im) = (cos(angle), sin(angle));
+        let t_re = w_re * o_re - w_im * o_im;
+        let t_im = w_re * o_im + w_im * o_re;
+
+        re[k] = e_re + t_re;
+        im[k] = e_im + t_im;
+        re[j] = e_re - t_re;
+        im[j] = t_im - e_im;
+    }
+}
+
+/// Computes the single-sided amplitude spectrum of an `rfft` result
+///
+/// `re` and `im` are the `n / 2 + 1` bins written by `rfft` and `amplitudes`
+/// must hold as many. With the rectangular window a sine wave of amplitude A
+/// that falls exactly on a bin shows up as A in that bin.
+pub fn amplitudes(re: &[f32], im: &[f32], amplitudes: &mut [f32]) {
+    let bins = re.len();
+    assert!(bins >= 2 && im.len() == bins);
+    assert!(amplitudes.len() >= bins);
+
+    let n = 2 * (bins - 1);
+    for (k, a) in amplitudes[..bins].iter_mut().enumerate() {
+        let magnitude = (re[k] * re[k] + im[k] * im[k]).sqrt();
+        let scale = if k == 0 || k == n / 2 { 1. } else { 2. };
+        *a = scale * magnitude / n as f32;
+    }
+}
+
+/// Returns the frequency of bin `k` of an `n` point FFT at sample rate `fs`
+pub fn bin_frequency(k: usize, n: usize, fs: f32) -> f32 {
+    k as f32 * fs / n as f32
+}
+
+/// Returns the phase of bin `k`, in radians
+pub fn phase(re: &[f32], im: &[f32], k: usize) -> f32 {
+    im[k].atan2(re[k])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f32], expected: &[f32], epsilon: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() <= epsilon, "[{}]: {} != {}", i, a, e);
+        }
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut average = MovingAverage::new([0; 4]);
+        let mut output = [0; 6];
+        average.filter(&[4, 8, 12, 16, 20, 24], &mut output);
+        assert_eq!(output, [4, 6, 8, 10, 14, 18]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn moving_average_empty_window() {
+        MovingAverage::new([0; 0]);
+    }
+
+    // reference: `order` boxcars of length `rate`, decimated and divided by
+    // rate ^ order
+    #[test]
+    fn cic() {
+        let input = [
+            100, 200, 300, 400, 500, 600, 700, 800, 900, 1000, 1100, 1200,
+        ];
+        let mut output = [0; 13];
+
+        let mut cic = Cic::new(2, 2);
+        assert_eq!(cic.gain(), 4);
+        let n = cic.decimate(&input, &mut output);
+        assert_eq!(&output[..n], &[100, 300, 500, 700, 900, 1100]);
+
+        let mut cic = Cic::new(3, 4);
+        assert_eq!(cic.gain(), 64);
+        let n = cic.decimate(&input, &mut output);
+        assert_eq!(&output[..n], &[54, 351, 750]);
+    }
+
+    #[test]
+    fn dc_blocker() {
+        let mut blocker = DcBlocker::new(0.5);
+        let mut output = [0.; 4];
+        blocker.filter(&[1, 1, 1, 1], &mut output);
+        assert_close(&output, &[1., 0.5, 0.25, 0.125], 1e-6);
+    }
+
+    #[test]
+    fn statistics() {
+        let x: [u16; 4] = [1, 2, 3, 4];
+        assert_close(&[mean(&x), rms(&x), ac_rms(&x)], &[2.5, 2.738_613, 1.118_034], 1e-5);
+
+        let y: [i32; 4] = [-3, 1, -1, 3];
+        assert_close(&[mean(&y), rms(&y), ac_rms(&y)], &[0., 2.236_068, 2.236_068], 1e-5);
+        assert_eq!(peak(&y), Some(Peak { index: 0, value: -3. }));
+
+        let empty: [i32; 0] = [];
+        assert_eq!(rms(&empty), 0.);
+        assert_eq!(peak(&empty), None);
+    }
+
+    #[test]
+    fn peaks() {
+        let x: [i16; 11] = [0, 5, 1, 0, 3, 0, 7, 6, 0, 2, 0];
+        let mut peaks = [0; 4];
+
+        let n = find_peaks(&x, 2., 3, &mut peaks);
+        assert_eq!(&peaks[..n], &[1, 6, 9]);
+
+        let n = find_peaks(&x, 4., 1, &mut peaks);
+        assert_eq!(&peaks[..n], &[1, 6]);
+
+        let n = find_peaks(&x, 2., 1, &mut peaks[..2]);
+        assert_eq!(n, 2);
+        assert_eq!(&peaks[..2], &[1, 4]);
+    }
+
+    #[test]
+    fn fft_reference() {
+        let mut re = [1., 2., 3., 4., 0., -1., -2., -3.];
+        let mut im = [0.; 8];
+        fft(&mut re, &mut im);
+
+        assert_close(
+            &re,
+            &[4., -1.828_427, 0., 3.828_427, 0., 3.828_427, 0., -1.828_427],
+            1e-4,
+        );
+        assert_close(
+            &im,
+            &[0., -12.071_068, 0., -2.071_068, 0., 2.071_068, 0., 12.071_068],
+            1e-4,
+        );
+    }
+
+    #[test]
+    fn rfft_reference() {
+        let x = [1., 2., 3., 4., 0., -1., -2., -3.];
+        let mut re = [0.; 5];
+        let mut im = [0.; 5];
+        rfft(&x, &mut re, &mut im);
+
+        assert_close(&re, &[4., -1.828_427, 0., 3.828_427, 0.], 1e-4);
+        assert_close(&im, &[0., -12.071_068, 0., -2.071_068, 0.], 1e-4);
+    }
+
+    #[test]
+    fn amplitude_spectrum() {
+        const N: usize = 32;
+
+        // 1 + 2 * cos(bin 2) + 0.5 * sin(bin 5)
+        let mut x = [0.; N];
+        for (i, x) in x.iter_mut().enumerate() {
+            let t = TWO_PI * i as f32 / N as f32;
+            *x = 1. + 2. * cos(2. * t) + 0.5 * sin(5. * t);
+        }
+
+        let mut re = [0.; N / 2 + 1];
+        let mut im = [0.; N / 2 + 1];
+        let mut a = [0.; N / 2 + 1];
+        rfft(&x, &mut re, &mut im);
+        amplitudes(&re, &im, &mut a);
+
+        let mut expected = [0.; N / 2 + 1];
+        expected[0] = 1.;
+        expected[2] = 2.;
+        expected[5] = 0.5;
+        assert_close(&a, &expected, 1e-3);
+    }
+}
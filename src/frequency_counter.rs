@@ -0,0 +1,364 @@
+//! Gated frequency counter
+//!
+//! TIM2 counts the edges of the input signal, clocked either from its ETR
+//! input (external clock mode 2) or from TI1 (external clock mode 1). TIM3
+//! runs in one-pulse mode and provides the gate: its TRGO output is high while
+//! it counts and, with the ETR source, TIM2 in gated slave mode on ITR2 only
+//! counts while the gate is open.
+//!
+//! External clock mode 1 is selected by the same SMS bits as the gated mode,
+//! so with the TI1 source TIM2 is started by `start` and stopped by `wait`
+//! when it sees the gate close. Edges arriving between the gate closing and
+//! `wait` noticing it are counted too, so poll `wait` in a tight loop and
+//! prefer long gate times with this source.
+//!
+//! # Input
+//!
+//! - TIM2_ETR / TIM2_CH1 = PA0
+//!
+//! The gate time is automatically ranged between 1 ms and 1 s so that the
+//! number of counted edges stays between `MIN_COUNT` and `MAX_COUNT`.
+
+use nb;
+use stm32f40x::{GPIOA, TIM2, TIM3, RCC};
+
+use time::Milliseconds;
+
+/// Gate times used when auto-ranging, in milliseconds
+const GATES: [u32; 4] = [1, 10, 100, 1_000];
+
+/// A measurement with fewer counts moves to a longer gate time
+pub const MIN_COUNT: u32 = 10_000;
+
+/// A measurement with more counts moves to a shorter gate time
+///
+/// The gates are a decade apart, so this is 20 times `MIN_COUNT`: a
+/// measurement that moves to the next gate lands between the two thresholds.
+/// The counter can't count faster than about a quarter of the timer clock,
+/// ~21 MHz at 84 MHz, which is ~21 000 counts in the 1 ms gate.
+pub const MAX_COUNT: u32 = 20 * MIN_COUNT;
+
+/// ETR input prescaler (ETPS bits of TIMx_SMCR)
+///
+/// The ETR signal must be slower than a quarter of the timer clock after
+/// prescaling.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EtrPrescaler {
+    /// No prescaler
+    _1 = 0b00,
+    /// Divide by 2
+    _2 = 0b01,
+    /// Divide by 4
+    _4 = 0b10,
+    /// Divide by 8
+    _8 = 0b11,
+}
+
+impl EtrPrescaler {
+    fn ratio(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// Signal that clocks the counter
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Source {
+    /// ETR input, external clock mode 2, gated in hardware
+    Etr(EtrPrescaler),
+    /// TI1 rising edges, external clock mode 1, gated in software
+    Ti1,
+}
+
+/// Result of a gated measurement
+#[derive(Clone, Copy, Debug)]
+pub struct Measurement {
+    /// Measured frequency, in Hz
+    pub frequency: f32,
+    /// Uncertainty of one count, in Hz
+    pub resolution: f32,
+    /// Number of edges counted during the gate
+    pub count: u32,
+    /// Gate time used for this measurement
+    pub gate: Milliseconds,
+}
+
+/// Frequency counter using TIM2 as counter and TIM3 as gate
+pub struct FrequencyCounter<'a> {
+    counter: &'a TIM2,
+    gate: &'a TIM3,
+    source: Source,
+    range: usize,
+    auto_range: bool,
+    /// Exact gate length, in APB1 ticks
+    gate_ticks: u32,
+}
+
+impl<'a> FrequencyCounter<'a> {
+    /// Creates a frequency counter, auto-ranging starts at a 100 ms gate
+    pub fn new(counter: &'a TIM2, gate: &'a TIM3, source: Source) -> Self {
+        FrequencyCounter {
+            counter: counter,
+            gate: gate,
+            source: source,
+            range: 2,
+            auto_range: true,
+            gate_ticks: 0,
+        }
+    }
+
+    /// Initializes the pin, both timers and the initial gate time
+    pub fn init(&mut self, gpioa: &GPIOA, rcc: &RCC) {
+        let tim2 = self.counter;
+        let tim3 = self.gate;
+
+        rcc.ahb1enr.modify(|_, w| w.gpioaen().set_bit());
+        rcc.apb1enr.modify(|_, w| w.tim2en().set_bit().tim3en().set_bit());
+
+        // PA0 = TIM2_ETR / TIM2_CH1, AF1
+        gpioa.afrl.modify(|_, w| unsafe { w.afrl0().bits(1) });
+        gpioa.moder.modify(|_, w| unsafe { w.moder0().bits(2) });
+
+        // RM0368 13.4.3
+        // SMS[2:0], TS[6:4], ETPS[13:12], ECE[14]
+        match self.source {
+            Source::Etr(psc) => {
+                // ECE: External clock mode 2 enabled
+                // TS: ITR2 = TIM3 TRGO
+                // SMS: Gated mode
+                tim2.smcr.write(|w| unsafe {
+                    w.bits((1 << 14) | ((psc as u32) << 12) | (0b010 << 4) | 0b101)
+                });
+            }
+            Source::Ti1 => {
+                // CC1S: IC1 mapped on TI1, no filter
+                tim2.ccmr1_output.modify(|r, w| unsafe { w.bits((r.bits() & !0xff) | 0b01) });
+                tim2.ccer.modify(|_, w| w.cc1p().clear_bit());
+                // TS: TI1FP1
+                // SMS: External clock mode 1
+                tim2.smcr.write(|w| unsafe { w.bits((0b101 << 4) | 0b111) });
+            }
+        }
+
+        tim2.psc.write(|w| unsafe { w.psc().bits(0) });
+        tim2.arr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        tim2.egr.write(|w| w.ug().set_bit());
+        tim2.cnt.write(|w| unsafe { w.bits(0) });
+        if let Source::Etr(_) = self.source {
+            // counts only while the gate is open
+            tim2.cr1.write(|w| w.cen().set_bit());
+        }
+
+        // RM0368 13.4.2
+        // MMS: 0b001 = Enable, TRGO follows CNT_EN
+        tim3.cr2.write(|w| unsafe { w.bits(0b001 << 4) });
+
+        let range = self.range;
+        self.set_range(range);
+    }
+
+    /// Enables or disables auto-ranging of the gate time
+    pub fn set_auto_range(&mut self, auto_range: bool) {
+        self.auto_range = auto_range;
+    }
+
+    /// Selects a fixed gate time, the closest of 1, 10, 100 and 1000 ms that
+    /// is not shorter than `gate`, and disables auto-ranging
+    pub fn set_gate(&mut self, gate: Milliseconds) {
+        let range = GATES
+            .iter()
+            .position(|ms| *ms >= gate.0)
+            .unwrap_or(GATES.len() - 1);
+        self.auto_range = false;
+        self.set_range(range);
+    }
+
+    /// Returns the current gate time
+    pub fn gate(&self) -> Milliseconds {
+        Milliseconds(GATES[self.range])
+    }
+
+    fn set_range(&mut self, range: usize) {
+        let tim3 = self.gate;
+        self.range = range;
+
+        let ticks = ::apb1::Ticks::from(Milliseconds(GATES[range])).0;
+        let (psc, arr) = gate_timing(ticks);
+        self.gate_ticks = arr * (psc + 1);
+
+        // one-pulse mode, only counter overflow generates an update interrupt
+        tim3.cr1.write(|w| w.opm().set_bit().urs().set_bit());
+        tim3.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+        tim3.arr.write(|w| unsafe { w.bits(arr - 1) });
+        // load the prescaler now rather than at the end of the first gate
+        tim3.egr.write(|w| w.ug().set_bit());
+        tim3.sr.modify(|_, w| w.uif().clear_bit());
+    }
+
+    /// Opens the gate and starts counting
+    pub fn start(&self) {
+        let tim2 = self.counter;
+        let tim3 = self.gate;
+
+        tim2.cnt.write(|w| unsafe { w.bits(0) });
+        tim3.cnt.write(|w| unsafe { w.bits(0) });
+        tim3.sr.modify(|_, w| w.uif().clear_bit());
+
+        if let Source::Ti1 = self.source {
+            tim2.cr1.modify(|_, w| w.cen().set_bit());
+        }
+        tim3.cr1.modify(|_, w| w.cen().set_bit());
+    }
+
+    /// Waits for the gate to close and returns the measurement
+    ///
+    /// With auto-ranging enabled the gate time for the next measurement is
+    /// adjusted when the count falls outside `MIN_COUNT ..= MAX_COUNT`. Call
+    /// `start` again to begin the next measurement.
+    pub fn wait(&mut self) -> nb::Result<Measurement, !> {
+        let tim2 = self.counter;
+        let tim3 = self.gate;
+
+        if tim3.sr.read().uif().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        if let Source::Ti1 = self.source {
+            tim2.cr1.modify(|_, w| w.cen().clear_bit());
+        }
+        tim3.sr.modify(|_, w| w.uif().clear_bit());
+
+        let count = tim2.cnt.read().bits();
+        let ratio = match self.source {
+            Source::Etr(psc) => psc.ratio(),
+            Source::Ti1 => 1,
+        };
+
+        let clock = ::apb1::Ticks::from(::time::Seconds(1)).0;
+        let resolution = resolution(ratio, self.gate_ticks, clock);
+
+        let measurement = Measurement {
+            frequency: count as f32 * resolution,
+            resolution: resolution,
+            count: count,
+            gate: self.gate(),
+        };
+
+        if self.auto_range {
+            let range = next_range(self.range, count);
+            if range != self.range {
+                self.set_range(range);
+            }
+        }
+
+        Ok(measurement)
+    }
+}
+
+/// Returns the gate range to use after measuring `count` edges with the gate
+/// `range`
+fn next_range(range: usize, count: u32) -> usize {
+    if count < MIN_COUNT && range + 1 < GATES.len() {
+        range + 1
+    } else if count > MAX_COUNT && range > 0 {
+        range - 1
+    } else {
+        range
+    }
+}
+
+/// Splits a gate of `ticks` timer clock cycles into the prescaler and the
+/// number of counts of the 16-bit TIM3, `(psc, arr)`
+///
+/// The gate lasts `arr * (psc + 1)` cycles, which is `ticks` rounded down to
+/// a multiple of `psc + 1`.
+fn gate_timing(ticks: u32) -> (u32, u32) {
+    let psc = (ticks - 1) / (1 << 16);
+    let arr = ticks / (psc + 1);
+    (psc, arr)
+}
+
+/// Frequency of one count of the counter, in Hz, for a gate of `gate_ticks`
+/// cycles of a `clock` Hz timer clock and an input divided by `ratio`
+fn resolution(ratio: u32, gate_ticks: u32, clock: u32) -> f32 {
+    ratio as f32 * clock as f32 / gate_ticks as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate_timing_fits_tim3() {
+        // 1 ms at 84 MHz needs a prescaler of 2 (PSC = 1)
+        assert_eq!(gate_timing(84_000), (1, 42_000));
+        assert_eq!(gate_timing(65_536), (0, 65_536));
+        assert_eq!(gate_timing(65_537), (1, 32_768));
+
+        for &ticks in &[1, 1_000, 84_000, 840_000, 8_400_000, 84_000_000, 100_000_000] {
+            let (psc, arr) = gate_timing(ticks);
+            assert!(psc <= 0xffff && arr >= 1 && arr <= 1 << 16);
+            let gate = arr * (psc + 1);
+            assert!(gate <= ticks && ticks - gate <= psc);
+        }
+    }
+
+    #[test]
+    fn resolution_of_a_gate() {
+        // a 1 s gate resolves 1 Hz, the ETR prescaler multiplies it
+        assert_eq!(resolution(1, 84_000_000, 84_000_000), 1.0);
+        assert_eq!(resolution(8, 84_000_000, 84_000_000), 8.0);
+        // a 1 ms gate resolves 1 kHz
+        assert_eq!(resolution(1, 84_000, 84_000_000), 1_000.0);
+    }
+
+    /// Counts of a `hz` input during the gate `range`
+    fn count(hz: u32, range: usize) -> u32 {
+        (u64::from(hz) * u64::from(GATES[range]) / 1_000) as u32
+    }
+
+    /// Auto-ranges from `range` until the gate doesn't change, returns the
+    /// gates it went through
+    fn settle(hz: u32, mut range: usize) -> ([usize; 4], usize) {
+        let mut ranges = [0; 4];
+        let mut n = 0;
+        loop {
+            ranges[n] = range;
+            n += 1;
+            let next = next_range(range, count(hz, range));
+            if next == range {
+                return (ranges, n);
+            }
+            range = next;
+        }
+    }
+
+    #[test]
+    fn auto_range_down_to_1_ms() {
+        // 21 MHz, about the fastest the counter can count at 84 MHz:
+        // 21 000 000, 2 100 000 and 210 000 counts are too many
+        assert_eq!(settle(21_000_000, 3), ([3, 2, 1, 0], 4));
+        assert_eq!(count(21_000_000, 0), 21_000);
+    }
+
+    #[test]
+    fn auto_range_up_to_1_s() {
+        // 5 kHz: 5, 50 and 500 counts are too few
+        assert_eq!(settle(5_000, 0), ([0, 1, 2, 3], 4));
+    }
+
+    #[test]
+    fn auto_range_settles() {
+        // every frequency settles on a gate moving in a single direction,
+        // never back and forth between two gates
+        for &hz in &[1, 999, 10_000, 150_000, 200_001, 1_000_000, 2_000_001, 9_999_999,
+                     21_000_000] {
+            for range in 0..GATES.len() {
+                let (ranges, n) = settle(hz, range);
+                let last = ranges[n - 1];
+                assert_eq!(next_range(last, count(hz, last)), last);
+                let step = ranges[n - 1] as i32 - ranges[0] as i32;
+                assert_eq!(step.abs() as usize, n - 1);
+            }
+        }
+    }
+}
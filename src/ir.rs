@@ -0,0 +1,581 @@
+//! Infrared remote control decoding (NEC and RC5)
+//!
+//! An IR receiver module (e.g. TSOP38238) outputs low while it sees a carrier
+//! burst ("mark") and high otherwise ("space"). Capture both edges of its
+//! output with `Capture` (`Polarity::Both`) and feed every timestamp to an
+//! `IrDecoder`.
+//!
+//! An edge that follows a long idle period is taken as the start of a mark,
+//! after that edges alternate between the end of a mark and the end of a
+//! space.
+//!
+//! The decoder is a plain state machine and doesn't touch any peripheral.
+
+use time::Microseconds;
+
+/// Decoder configuration
+#[derive(Clone, Copy, Debug)]
+pub struct IrConfig {
+    /// Capture counter ticks per microsecond
+    pub ticks_per_us: u32,
+    /// Mask applied to timestamp differences, `0xffff` for 16-bit timers
+    pub counter_mask: u32,
+    /// Accepted deviation from the nominal timings, in percent
+    pub tolerance: u32,
+    /// Any interval at least this long ends a frame
+    pub idle: Microseconds,
+}
+
+impl Default for IrConfig {
+    /// 1 MHz capture counter on a 16-bit timer, 25% tolerance, 10 ms idle
+    fn default() -> Self {
+        IrConfig {
+            ticks_per_us: 1,
+            counter_mask: 0xffff,
+            tolerance: 25,
+            idle: Microseconds(10_000),
+        }
+    }
+}
+
+/// A decoded remote control event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// NEC frame, `address` is 8 bits unless the remote uses extended NEC
+    Nec {
+        /// Device address
+        address: u16,
+        /// Command
+        command: u8,
+    },
+    /// NEC repeat code, the last key is still held down
+    NecRepeat,
+    /// RC5 frame
+    Rc5 {
+        /// Device address, 5 bits
+        address: u8,
+        /// Command, 7 bits including the RC5X field bit
+        command: u8,
+        /// Toggles every time a key is pressed again
+        toggle: bool,
+    },
+}
+
+fn within(actual: u32, nominal: u32, tolerance: u32) -> bool {
+    let delta = nominal * tolerance / 100;
+    actual + delta >= nominal && actual <= nominal + delta
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NecState {
+    LeaderMark,
+    LeaderSpace,
+    BitMark(u32),
+    BitSpace(u32),
+    StopMark,
+    RepeatMark,
+    Error,
+}
+
+/// NEC protocol decoder
+///
+/// 9 ms leader mark, 4.5 ms space (2.25 ms for a repeat code) and 32 bits,
+/// LSB first: a 562.5 us mark followed by a 562.5 us (0) or 1687.5 us (1)
+/// space, then a final 562.5 us mark.
+pub struct NecDecoder {
+    state: NecState,
+    data: u32,
+}
+
+impl NecDecoder {
+    /// Creates a decoder that waits for a leader mark
+    pub const fn new() -> Self {
+        NecDecoder {
+            state: NecState::LeaderMark,
+            data: 0,
+        }
+    }
+
+    /// Waits for the next leader mark
+    pub fn reset(&mut self) {
+        self.state = NecState::LeaderMark;
+        self.data = 0;
+    }
+
+    /// Feeds the duration, in microseconds, of a mark or a space
+    pub fn step(&mut self, mark: bool, us: u32, tolerance: u32) -> Option<Event> {
+        let short = within(us, 562, tolerance);
+
+        let (state, event) = match (self.state, mark) {
+            (NecState::LeaderMark, true) if within(us, 9_000, tolerance) => {
+                (NecState::LeaderSpace, None)
+            }
+            (NecState::LeaderSpace, false) if within(us, 4_500, tolerance) => {
+                self.data = 0;
+                (NecState::BitMark(0), None)
+            }
+            (NecState::LeaderSpace, false) if within(us, 2_250, tolerance) => {
+                (NecState::RepeatMark, None)
+            }
+            (NecState::BitMark(n), true) if short => (NecState::BitSpace(n), None),
+            (NecState::BitSpace(n), false) if short || within(us, 1_687, tolerance) => {
+                if !short {
+                    self.data |= 1 << n;
+                }
+                if n == 31 {
+                    (NecState::StopMark, None)
+                } else {
+                    (NecState::BitMark(n + 1), None)
+                }
+            }
+            (NecState::StopMark, true) if short => (NecState::Error, decode_nec(self.data)),
+            (NecState::RepeatMark, true) if short => (NecState::Error, Some(Event::NecRepeat)),
+            _ => (NecState::Error, None),
+        };
+
+        self.state = state;
+        event
+    }
+}
+
+fn decode_nec(data: u32) -> Option<Event> {
+    let address = data & 0xff;
+    let naddress = (data >> 8) & 0xff;
+    let command = (data >> 16) & 0xff;
+    let ncommand = data >> 24;
+
+    if command ^ ncommand != 0xff {
+        return None;
+    }
+
+    let address = if address ^ naddress == 0xff {
+        address as u16
+    } else {
+        // extended NEC, 16-bit address
+        (data & 0xffff) as u16
+    };
+
+    Some(Event::Nec {
+        address: address,
+        command: command as u8,
+    })
+}
+
+/// Number of half bits in an RC5 frame
+const RC5_HALVES: usize = 28;
+
+/// RC5 protocol decoder
+///
+/// 14 Manchester coded bits of 1.778 ms, MSB first: two start bits (the
+/// second one is the inverted command bit 6 in RC5X), a toggle bit, 5 address
+/// bits and 6 command bits. A one is a space followed by a mark.
+pub struct Rc5Decoder {
+    /// `true` for a mark
+    halves: [bool; RC5_HALVES],
+    len: usize,
+}
+
+impl Rc5Decoder {
+    /// Creates a decoder that waits for the first mark of a frame
+    pub const fn new() -> Self {
+        Rc5Decoder {
+            halves: [false; RC5_HALVES],
+            // the first half of the first start bit is an invisible space
+            len: 1,
+        }
+    }
+
+    /// Waits for the first mark of the next frame
+    pub fn reset(&mut self) {
+        self.len = 1;
+    }
+
+    /// Feeds the duration, in microseconds, of a mark or a space
+    pub fn step(&mut self, mark: bool, us: u32, tolerance: u32) -> Option<Event> {
+        if self.len >= RC5_HALVES {
+            return None;
+        }
+
+        let n = if within(us, 889, tolerance) {
+            1
+        } else if within(us, 1_778, tolerance) {
+            2
+        } else {
+            self.len = RC5_HALVES;
+            return None;
+        };
+
+        for _ in 0..n {
+            if self.len == RC5_HALVES {
+                return None;
+            }
+            self.halves[self.len] = mark;
+            self.len += 1;
+        }
+
+        // a frame that ends with a zero ends with a space that no edge
+        // terminates
+        if self.len == RC5_HALVES - 1 && self.halves[RC5_HALVES - 2] {
+            self.halves[RC5_HALVES - 1] = false;
+            self.len = RC5_HALVES;
+        }
+
+        if self.len == RC5_HALVES {
+            self.decode()
+        } else {
+            None
+        }
+    }
+
+    fn decode(&self) -> Option<Event> {
+        let mut bits: u16 = 0;
+        for pair in self.halves.chunks(2) {
+            if pair[0] == pair[1] {
+                return None;
+            }
+            bits = (bits << 1) | pair[1] as u16;
+        }
+
+        if bits & (1 << 13) == 0 {
+            return None;
+        }
+
+        let field = bits & (1 << 12) != 0;
+        let toggle = bits & (1 << 11) != 0;
+        let address = ((bits >> 6) & 0x1f) as u8;
+        let command = (bits & 0x3f) as u8 | if field { 0 } else { 1 << 6 };
+
+        Some(Event::Rc5 {
+            address: address,
+            command: command,
+            toggle: toggle,
+        })
+    }
+}
+
+/// NEC and RC5 decoder fed with edge timestamps
+pub struct IrDecoder {
+    config: IrConfig,
+    last: Option<u32>,
+    mark: bool,
+    nec: NecDecoder,
+    rc5: Rc5Decoder,
+}
+
+impl IrDecoder {
+    /// Creates a decoder
+    pub fn new(config: IrConfig) -> Self {
+        IrDecoder {
+            config: config,
+            last: None,
+            mark: false,
+            nec: NecDecoder::new(),
+            rc5: Rc5Decoder::new(),
+        }
+    }
+
+    /// Feeds the timestamp of an edge
+    pub fn feed(&mut self, timestamp: u32) -> Option<Event> {
+        let last = self.last;
+        self.last = Some(timestamp);
+
+        let us = match last {
+            Some(last) => {
+                (timestamp.wrapping_sub(last) & self.config.counter_mask) / self.config.ticks_per_us
+            }
+            None => self.config.idle.0,
+        };
+
+        if us >= self.config.idle.0 {
+            // this edge starts the first mark of a new frame
+            self.nec.reset();
+            self.rc5.reset();
+            self.mark = true;
+            return None;
+        }
+
+        let mark = self.mark;
+        self.mark = !mark;
+
+        let tolerance = self.config.tolerance;
+        let nec = self.nec.step(mark, us, tolerance);
+        let rc5 = self.rc5.step(mark, us, tolerance);
+        nec.or(rc5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mark and space durations of a frame, starting with a mark
+    struct Durations {
+        us: [u32; 72],
+        len: usize,
+    }
+
+    impl Durations {
+        fn new() -> Self {
+            Durations {
+                us: [0; 72],
+                len: 0,
+            }
+        }
+
+        fn push(&mut self, us: u32) {
+            self.us[self.len] = us;
+            self.len += 1;
+        }
+    }
+
+    // nominal timings as sent by an actual remote, not the ones the decoder
+    // checks against
+    fn nec(data: u32) -> Durations {
+        let mut durations = Durations::new();
+        durations.push(9_000);
+        durations.push(4_500);
+        for n in 0..32 {
+            durations.push(560);
+            durations.push(if data & (1 << n) != 0 { 1_690 } else { 560 });
+        }
+        durations.push(560);
+        durations
+    }
+
+    fn nec_repeat() -> Durations {
+        let mut durations = Durations::new();
+        durations.push(9_000);
+        durations.push(2_250);
+        durations.push(560);
+        durations
+    }
+
+    /// `bits` are the 14 bits of the frame, start bits included
+    fn rc5(bits: u16) -> Durations {
+        // the first half of the first start bit is an invisible space
+        let mut halves = [false; 27];
+        for n in 0..14 {
+            let one = bits & (1 << (13 - n)) != 0;
+            if n > 0 {
+                halves[2 * n - 1] = !one;
+            }
+            halves[2 * n] = one;
+        }
+
+        let mut durations = Durations::new();
+        let mut run = 0;
+        for (i, half) in halves.iter().enumerate() {
+            run += 889;
+            if i + 1 == halves.len() || halves[i + 1] != *half {
+                // the space that ends a frame isn't terminated by an edge
+                if *half || i + 1 != halves.len() {
+                    durations.push(run);
+                }
+                run = 0;
+            }
+        }
+        durations
+    }
+
+    /// Feeds the edges of `durations` to `decoder` as timestamps of a 16-bit
+    /// timer running at 1 MHz, starting at `start`. Returns the timestamp of
+    /// the last edge and the event it produced; no other edge may produce one
+    fn feed(decoder: &mut IrDecoder, start: u32, durations: &Durations) -> (u32, Option<Event>) {
+        let mut timestamp = start & 0xffff;
+        assert_eq!(decoder.feed(timestamp), None);
+
+        let mut event = None;
+        for (i, us) in durations.us[..durations.len].iter().enumerate() {
+            timestamp = (timestamp + *us) & 0xffff;
+            let e = decoder.feed(timestamp);
+            if i + 1 == durations.len {
+                event = e;
+            } else {
+                assert_eq!(e, None);
+            }
+        }
+        (timestamp, event)
+    }
+
+    #[test]
+    fn nec_frames() {
+        let mut decoder = IrDecoder::new(IrConfig::default());
+
+        // address 0x04, command 0x08
+        let (t, event) = feed(&mut decoder, 0, &nec(0xf708_fb04));
+        assert_eq!(
+            event,
+            Some(Event::Nec {
+                address: 0x04,
+                command: 0x08,
+            })
+        );
+
+        // key held down, the repeat code follows ~40 ms after the frame
+        let (t, event) = feed(&mut decoder, t + 40_000, &nec_repeat());
+        assert_eq!(event, Some(Event::NecRepeat));
+        // and then every 108 ms
+        let (t, event) = feed(&mut decoder, t + 96_000, &nec_repeat());
+        assert_eq!(event, Some(Event::NecRepeat));
+
+        // extended NEC, 16-bit address
+        let (t, event) = feed(&mut decoder, t + 40_000, &nec(0xbf40_1234));
+        assert_eq!(
+            event,
+            Some(Event::Nec {
+                address: 0x1234,
+                command: 0x40,
+            })
+        );
+
+        // the command doesn't match its inverse
+        let (_, event) = feed(&mut decoder, t + 40_000, &nec(0xf709_fb04));
+        assert_eq!(event, None);
+    }
+
+    #[test]
+    fn rc5_frames() {
+        let mut decoder = IrDecoder::new(IrConfig::default());
+
+        // start bits 11, toggle 0, address 5, command 53
+        let (t, event) = feed(&mut decoder, 0, &rc5(0b11_0_00101_110101));
+        assert_eq!(
+            event,
+            Some(Event::Rc5 {
+                address: 5,
+                command: 53,
+                toggle: false,
+            })
+        );
+
+        // key pressed again, the frame ends with a zero
+        let (t, event) = feed(&mut decoder, t + 20_000, &rc5(0b11_1_00101_001010));
+        assert_eq!(
+            event,
+            Some(Event::Rc5 {
+                address: 5,
+                command: 10,
+                toggle: true,
+            })
+        );
+
+        // RC5X, the second start bit is the inverted command bit 6
+        let (_, event) = feed(&mut decoder, t + 20_000, &rc5(0b10_0_11111_000110));
+        assert_eq!(
+            event,
+            Some(Event::Rc5 {
+                address: 31,
+                command: 70,
+                toggle: false,
+            })
+        );
+    }
+
+    #[test]
+    fn rc5_invalid_timing() {
+        let mut decoder = IrDecoder::new(IrConfig::default());
+
+        let mut durations = rc5(0b11_0_00101_110101);
+        durations.us[3] = 1_300;
+        let (t, event) = feed(&mut decoder, 0, &durations);
+        assert_eq!(event, None);
+
+        // the decoder recovers on the next frame
+        let (_, event) = feed(&mut decoder, t + 20_000, &rc5(0b11_0_00101_110101));
+        assert!(event.is_some());
+    }
+
+    // Edge timestamps of a 1 MHz, 16-bit capture counter as a TSOP38238 style
+    // receiver outputs them: marks come out ~75 us longer and spaces ~75 us
+    // shorter than sent, with +-20 us of jitter on every edge, and the counter
+    // wraps within the frames.
+
+    /// NEC address 0x00, command 0x45, then a repeat code 40 ms later
+    const NEC_TRACE: [u16; 72] = [
+        0xd120, 0xf493, 0x05d1, 0x0853, 0x0a29, 0x0c96, 0x0e8b, 0x10fa,
+        0x12e4, 0x1572, 0x1748, 0x19d1, 0x1bb1, 0x1e1c, 0x1ff4, 0x2278,
+        0x2465, 0x26d2, 0x28b4, 0x2b22, 0x317d, 0x3401, 0x3a3c, 0x3cc9,
+        0x4308, 0x457f, 0x4bdf, 0x4e70, 0x54cd, 0x5739, 0x5d95, 0x6023,
+        0x6674, 0x68e0, 0x6f26, 0x7191, 0x77ec, 0x7a5d, 0x7c42, 0x7ec5,
+        0x8506, 0x8791, 0x896b, 0x8bf8, 0x8dde, 0x906a, 0x9248, 0x94b7,
+        0x9b14, 0x9da1, 0x9f9c, 0xa211, 0xa3fb, 0xa66a, 0xacc5, 0xaf32,
+        0xb129, 0xb395, 0xb9f4, 0xbc6a, 0xc2c1, 0xc54c, 0xcb9f, 0xce1c,
+        0xd00c, 0xd29a, 0xd8ef, 0xdb6f, 0x77af, 0x9b21, 0xa39b, 0xa60f,
+    ];
+
+    /// RC5 address 20, command 12, then the same key pressed again 20 ms
+    /// later with the toggle bit set
+    const RC5_TRACE: [u16; 42] = [
+        0x7a10, 0x7dcf, 0x80ee, 0x883b, 0x8ee1, 0x962b, 0x9cdd, 0xa41b,
+        0xa751, 0xab13, 0xae53, 0xb207, 0xb528, 0xb8f8, 0xbfa5, 0xc35f,
+        0xc68e, 0xcdc0, 0xd0f9, 0xd4c3, 0x22e3, 0x2695, 0x29b3, 0x2d86,
+        0x30c4, 0x3488, 0x37b7, 0x3ef6, 0x45af, 0x4cf7, 0x5036, 0x5403,
+        0x5721, 0x5ad6, 0x5e01, 0x61cf, 0x6866, 0x6c19, 0x6f46, 0x7693,
+        0x79c9, 0x7d8b,
+    ];
+
+    /// Feeds every timestamp of `trace` and returns the events, in order
+    fn decode_trace(decoder: &mut IrDecoder, trace: &[u16]) -> ([Option<Event>; 4], usize) {
+        let mut events = [None; 4];
+        let mut n = 0;
+        for timestamp in trace {
+            if let Some(event) = decoder.feed(u32::from(*timestamp)) {
+                events[n] = Some(event);
+                n += 1;
+            }
+        }
+        (events, n)
+    }
+
+    #[test]
+    fn nec_trace() {
+        let mut decoder = IrDecoder::new(IrConfig::default());
+
+        let (events, n) = decode_trace(&mut decoder, &NEC_TRACE);
+        assert_eq!(n, 2);
+        assert_eq!(
+            events[0],
+            Some(Event::Nec {
+                address: 0x00,
+                command: 0x45,
+            })
+        );
+        assert_eq!(events[1], Some(Event::NecRepeat));
+    }
+
+    #[test]
+    fn rc5_trace() {
+        let mut decoder = IrDecoder::new(IrConfig::default());
+
+        let (events, n) = decode_trace(&mut decoder, &RC5_TRACE);
+        assert_eq!(n, 2);
+        assert_eq!(
+            events[0],
+            Some(Event::Rc5 {
+                address: 20,
+                command: 12,
+                toggle: false,
+            })
+        );
+        assert_eq!(
+            events[1],
+            Some(Event::Rc5 {
+                address: 20,
+                command: 12,
+                toggle: true,
+            })
+        );
+    }
+
+    #[test]
+    fn traces_outside_a_tight_tolerance() {
+        // the receiver's mark / space skew alone is more than 5%
+        let mut decoder = IrDecoder::new(IrConfig {
+            tolerance: 5,
+            ..IrConfig::default()
+        });
+
+        assert_eq!(decode_trace(&mut decoder, &NEC_TRACE).1, 0);
+        assert_eq!(decode_trace(&mut decoder, &RC5_TRACE).1, 0);
+    }
+}
@@ -41,22 +41,36 @@ pub mod clock;
 pub mod spi;
 pub mod lsm9ds1;
 pub mod frequency;
+pub mod frequency_counter;
 pub mod madgwick_ahrs;
 pub mod dwt;
+pub mod dshot;
+pub mod dsp;
 pub mod adc;
+pub mod dac;
 pub mod i2c;
+pub mod ir;
+pub mod motor;
+pub mod rc;
+pub mod servo;
+pub mod ws2812;
 
 use frequency::*;
 
-pub use adc::{Adc1, Adc2, AdcChannel};
-pub use capture::Capture;
+pub use adc::{Adc, AdcChannel, Calibration, DualSample, OneShot, Resolution, SampleTime,
+              Sequence, TripleSample};
+pub use capture::{Capture, Edges, InputConfig, PwmInput};
+pub use dac::{Dac, DacChannel};
 pub use hal::prelude;
+pub use frequency_counter::FrequencyCounter;
 pub use i2c::I2c;
 pub use lsm9ds1::{ImuSettings, Lsm9ds1};
 pub use madgwick_ahrs::MadgwickAhrs;
 pub use math_utils::{Quaternion, Vector3};
+pub use motor::Inverter;
 pub use pwm::Pwm;
 pub use serial::{Serial, U8Writer};
+pub use servo::{Servo, ServoGroup};
 pub use spi::Spi;
 pub use timer::{Channel, Timer};
 
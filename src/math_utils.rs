@@ -1,5 +1,6 @@
 //! Some math utilities
 
+use core::f32::consts;
 use core::mem;
 use m::Float as _0;
 
@@ -200,3 +201,34 @@ pub fn fast_inv_sqrt(x: f32) -> f32 {
     let y: f32 = unsafe { mem::transmute(j) };
     y * (1.5 - 0.5 * x * y * y)
 }
+
+/// Pi
+pub const PI: f32 = consts::PI;
+/// Two times pi
+pub const TWO_PI: f32 = 2.0 * PI;
+
+/// Sine of `x` radians
+///
+/// Range reduction to [-pi/2, pi/2] followed by a 9th order Taylor polynomial,
+/// the absolute error is below 4e-6.
+pub fn sin(x: f32) -> f32 {
+    // reduce to [-pi, pi]
+    let k = x / TWO_PI;
+    let k = if k >= 0.0 { (k + 0.5) as i32 } else { (k - 0.5) as i32 };
+    let mut r = x - k as f32 * TWO_PI;
+
+    // reduce to [-pi/2, pi/2] using sin(pi - x) = sin(x)
+    if r > PI / 2.0 {
+        r = PI - r;
+    } else if r < -PI / 2.0 {
+        r = -PI - r;
+    }
+
+    let r2 = r * r;
+    r * (1.0 - r2 / 6.0 * (1.0 - r2 / 20.0 * (1.0 - r2 / 42.0 * (1.0 - r2 / 72.0))))
+}
+
+/// Cosine of `x` radians
+pub fn cos(x: f32) -> f32 {
+    sin(x + PI / 2.0)
+}
@@ -0,0 +1,331 @@
+//! Three-phase inverter control on TIM1
+//!
+//! Turns a voltage vector into three synchronised phase duties using either
+//! sinusoidal or space-vector modulation (SVPWM).
+//!
+//! # TIM1
+//!
+//! - Phase A = CH1 = PA8
+//! - Phase B = CH2 = PA9
+//! - Phase C = CH3 = PA10
+//!
+//! Voltages are normalized to the DC bus voltage, so a phase voltage of `0.5`
+//! equals half the bus voltage. The linear range is a vector magnitude of
+//! `0.5` for sinusoidal modulation and `1 / sqrt(3)` (~0.577) for SVPWM.
+//!
+//! The transforms and the modulators don't touch any peripheral and can be
+//! checked against reference tables on the host.
+
+use hal::prelude::*;
+use stm32f40x::{TIM1, GPIOA, GPIOB, GPIOC, RCC};
+
+use math_utils::{cos, sin};
+use pwm::{Alignment, HoldUpdates, Pwm};
+use timer::Channel;
+
+/// `1 / sqrt(3)`
+const FRAC_1_SQRT_3: f32 = 0.577350269;
+/// `sqrt(3) / 2`
+const SQRT_3_2: f32 = 0.866025404;
+
+/// Modulation scheme
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Modulation {
+    /// Sine-triangle modulation, each phase follows its reference voltage
+    Sinusoidal,
+    /// Space-vector modulation implemented as min-max zero sequence injection
+    SpaceVector,
+}
+
+/// Stationary two-phase (alpha / beta) quantity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlphaBeta {
+    /// Alpha axis value
+    pub alpha: f32,
+    /// Beta axis value
+    pub beta: f32,
+}
+
+/// Rotating two-phase (d / q) quantity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dq {
+    /// Direct axis value
+    pub d: f32,
+    /// Quadrature axis value
+    pub q: f32,
+}
+
+/// Three-phase quantity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Abc {
+    /// Phase A value
+    pub a: f32,
+    /// Phase B value
+    pub b: f32,
+    /// Phase C value
+    pub c: f32,
+}
+
+/// Amplitude invariant Clarke transform, assumes `a + b + c = 0`
+pub fn clarke(abc: Abc) -> AlphaBeta {
+    AlphaBeta {
+        alpha: abc.a,
+        beta: (abc.a + 2.0 * abc.b) * FRAC_1_SQRT_3,
+    }
+}
+
+/// Inverse Clarke transform
+pub fn inverse_clarke(ab: AlphaBeta) -> Abc {
+    Abc {
+        a: ab.alpha,
+        b: -0.5 * ab.alpha + SQRT_3_2 * ab.beta,
+        c: -0.5 * ab.alpha - SQRT_3_2 * ab.beta,
+    }
+}
+
+/// Park transform, `theta` is the electrical angle in radians
+pub fn park(ab: AlphaBeta, theta: f32) -> Dq {
+    let (s, c) = (sin(theta), cos(theta));
+    Dq {
+        d: ab.alpha * c + ab.beta * s,
+        q: -ab.alpha * s + ab.beta * c,
+    }
+}
+
+/// Inverse Park transform, `theta` is the electrical angle in radians
+pub fn inverse_park(dq: Dq, theta: f32) -> AlphaBeta {
+    let (s, c) = (sin(theta), cos(theta));
+    AlphaBeta {
+        alpha: dq.d * c - dq.q * s,
+        beta: dq.d * s + dq.q * c,
+    }
+}
+
+/// Voltage vector of magnitude `magnitude` at `angle` radians
+pub fn polar(magnitude: f32, angle: f32) -> AlphaBeta {
+    AlphaBeta {
+        alpha: magnitude * cos(angle),
+        beta: magnitude * sin(angle),
+    }
+}
+
+/// Computes the phase duty cycles, in the range `0.0 ..= 1.0`, that produce
+/// the normalized voltage vector `v`
+///
+/// Vectors outside the linear range of `modulation` are clipped per phase.
+pub fn duties(v: AlphaBeta, modulation: Modulation) -> Abc {
+    let abc = inverse_clarke(v);
+
+    let offset = match modulation {
+        Modulation::Sinusoidal => 0.0,
+        Modulation::SpaceVector => {
+            let max = max3(abc.a, abc.b, abc.c);
+            let min = min3(abc.a, abc.b, abc.c);
+            -(max + min) / 2.0
+        }
+    };
+
+    Abc {
+        a: clamp(0.5 + abc.a + offset),
+        b: clamp(0.5 + abc.b + offset),
+        c: clamp(0.5 + abc.c + offset),
+    }
+}
+
+fn max3(a: f32, b: f32, c: f32) -> f32 {
+    let m = if a > b { a } else { b };
+    if m > c { m } else { c }
+}
+
+fn min3(a: f32, b: f32, c: f32) -> f32 {
+    let m = if a < b { a } else { b };
+    if m < c { m } else { c }
+}
+
+fn clamp(x: f32) -> f32 {
+    if x < 0.0 {
+        0.0
+    } else if x > 1.0 {
+        1.0
+    } else {
+        x
+    }
+}
+
+/// Three-phase inverter driven by channels 1, 2 and 3 of TIM1
+pub struct Inverter<'a> {
+    pwm: Pwm<'a, TIM1>,
+    modulation: Modulation,
+}
+
+impl<'a> Inverter<'a> {
+    /// Creates an inverter on top of `tim1`
+    pub fn new(tim1: &'a TIM1, modulation: Modulation) -> Self {
+        Inverter {
+            pwm: Pwm(tim1),
+            modulation: modulation,
+        }
+    }
+
+    /// Initializes the three phase outputs with a switching period of
+    /// `period`
+    ///
+    /// The timer runs in center-aligned mode 1 so the switching period is
+    /// twice `period`. All phases start at 50% duty (zero voltage vector) and
+    /// are left disabled, call `enable` to start switching.
+    pub fn init<P>(&self, period: P, gpioa: &GPIOA, gpiob: &GPIOB, gpioc: &GPIOC, rcc: &RCC)
+    where
+        P: Into<::apb2::Ticks>,
+    {
+        let period = period.into();
+        for c in &[Channel::_1, Channel::_2, Channel::_3] {
+            self.pwm.init(period, *c, None, gpioa, gpiob, gpioc, rcc);
+        }
+        self.pwm.set_alignment(Alignment::Center1);
+        self.set_duties(Abc { a: 0.5, b: 0.5, c: 0.5 });
+    }
+
+    /// Enables the three phase outputs
+    pub fn enable(&self) {
+        self.pwm.enable(Channel::_1);
+        self.pwm.enable(Channel::_2);
+        self.pwm.enable(Channel::_3);
+    }
+
+    /// Disables the three phase outputs
+    pub fn disable(&self) {
+        self.pwm.disable(Channel::_1);
+        self.pwm.disable(Channel::_2);
+        self.pwm.disable(Channel::_3);
+    }
+
+    /// Changes the modulation scheme
+    pub fn set_modulation(&mut self, modulation: Modulation) {
+        self.modulation = modulation;
+    }
+
+    /// Applies the normalized stationary voltage vector `v`
+    pub fn set_voltage(&self, v: AlphaBeta) {
+        self.set_duties(duties(v, self.modulation));
+    }
+
+    /// Applies a normalized voltage vector given as `magnitude` and electrical
+    /// `angle` in radians
+    pub fn set_voltage_polar(&self, magnitude: f32, angle: f32) {
+        self.set_voltage(polar(magnitude, angle));
+    }
+
+    /// Applies a normalized rotating voltage vector at electrical angle
+    /// `theta`
+    pub fn set_voltage_dq(&self, v: Dq, theta: f32) {
+        self.set_voltage(inverse_park(v, theta));
+    }
+
+    /// Writes the three phase duty cycles, in the range `0.0 ..= 1.0`
+    ///
+    /// The compare registers are preloaded and update events are held off
+    /// while writing them, so all three phases change on the same PWM period.
+    pub fn set_duties(&self, duties: Abc) {
+        let max = f32::from(self.pwm.get_max_duty());
+
+        self.pwm.hold_updates(true);
+        self.pwm.set_duty(Channel::_1, (clamp(duties.a) * max) as u16);
+        self.pwm.set_duty(Channel::_2, (clamp(duties.b) * max) as u16);
+        self.pwm.set_duty(Channel::_3, (clamp(duties.c) * max) as u16);
+        self.pwm.hold_updates(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math_utils::PI;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_abc(actual: Abc, expected: [f32; 3]) {
+        let actual = [actual.a, actual.b, actual.c];
+        for (a, e) in actual.iter().zip(&expected) {
+            assert!((a - e).abs() <= EPSILON, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn assert_ab(actual: AlphaBeta, alpha: f32, beta: f32) {
+        assert!(
+            (actual.alpha - alpha).abs() <= EPSILON && (actual.beta - beta).abs() <= EPSILON,
+            "{:?} != ({}, {})",
+            actual,
+            alpha,
+            beta
+        );
+    }
+
+    #[test]
+    fn clarke_transforms() {
+        let ab = clarke(Abc { a: 1.0, b: -0.5, c: -0.5 });
+        assert_ab(ab, 1.0, 0.0);
+
+        let ab = clarke(Abc { a: 0.0, b: SQRT_3_2, c: -SQRT_3_2 });
+        assert_ab(ab, 0.0, 1.0);
+
+        let abc = inverse_clarke(AlphaBeta { alpha: 0.0, beta: 1.0 });
+        assert_abc(abc, [0.0, 0.866_025, -0.866_025]);
+    }
+
+    #[test]
+    fn park_transforms() {
+        let dq = park(AlphaBeta { alpha: 1.0, beta: 0.0 }, PI / 2.0);
+        assert!(dq.d.abs() <= EPSILON && (dq.q + 1.0).abs() <= EPSILON);
+
+        let ab = inverse_park(Dq { d: 1.0, q: 0.0 }, PI / 3.0);
+        assert_ab(ab, 0.5, 0.866_025);
+
+        let ab = inverse_park(Dq { d: 0.0, q: 1.0 }, PI / 3.0);
+        assert_ab(ab, -0.866_025, 0.5);
+
+        // round trip
+        let dq = park(inverse_park(Dq { d: 0.3, q: -0.7 }, 2.5), 2.5);
+        assert!((dq.d - 0.3).abs() <= EPSILON && (dq.q + 0.7).abs() <= EPSILON);
+    }
+
+    // reference: classical SVPWM, dwell times T1 = sqrt(3) * m * sin(60 - phi)
+    // and T2 = sqrt(3) * m * sin(phi) of the two active vectors of the sector,
+    // zero vectors split evenly
+    #[test]
+    fn space_vector_sectors() {
+        const TABLE: [(f32, [f32; 3]); 8] = [
+            (0.0, [0.875, 0.125, 0.125]),
+            (30.0, [0.933_013, 0.5, 0.066_987]),
+            (45.0, [0.918_258, 0.694_114, 0.081_742]),
+            (90.0, [0.5, 0.933_013, 0.066_987]),
+            (150.0, [0.066_987, 0.933_013, 0.5]),
+            (210.0, [0.066_987, 0.5, 0.933_013]),
+            (270.0, [0.5, 0.066_987, 0.933_013]),
+            (330.0, [0.933_013, 0.066_987, 0.5]),
+        ];
+
+        for &(degrees, expected) in TABLE.iter() {
+            let v = polar(0.5, degrees * PI / 180.0);
+            assert_abc(duties(v, Modulation::SpaceVector), expected);
+        }
+    }
+
+    #[test]
+    fn space_vector_linear_limit() {
+        let v = polar(FRAC_1_SQRT_3, 0.0);
+        assert_abc(duties(v, Modulation::SpaceVector), [0.933_013, 0.066_987, 0.066_987]);
+
+        let v = polar(FRAC_1_SQRT_3, PI / 6.0);
+        assert_abc(duties(v, Modulation::SpaceVector), [1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn sinusoidal() {
+        let v = polar(0.5, 0.0);
+        assert_abc(duties(v, Modulation::Sinusoidal), [1.0, 0.25, 0.25]);
+
+        // clipped outside of the linear range
+        let v = polar(0.6, 0.0);
+        assert_abc(duties(v, Modulation::Sinusoidal), [1.0, 0.2, 0.2]);
+    }
+}
@@ -29,6 +29,13 @@
 //! - CH2 = PB7
 //! - CH3 = PB8
 //! - CH4 = PB9
+//!
+//! # Modes
+//!
+//! `Pwm::init` configures edge-aligned PWM mode 1 with active high outputs.
+//! Use `set_alignment`, `set_polarity`, `set_output_mode` and `set_preload`
+//! to select center-aligned counting, PWM mode 2 or one of the other output
+//! compare modes afterwards.
 
 use core::any::{Any, TypeId};
 use core::marker::Unsize;
@@ -38,14 +45,95 @@ use hal;
 use static_ref::Static;
 use stm32f40x::{DMA1, TIM1, TIM2, TIM3, TIM4, GPIOA, GPIOB, GPIOC, RCC};
 
-use dma::{self, Buffer, Dma1Stream2};
+use dma::{self, Buffer, Direction, Dma1Stream2, DmaChannel, Flags, Priority, Size};
 use timer::Channel;
 
 /// PWM driver
+///
+/// Besides PWM, the output channels can be used as plain output compare
+/// generators, see `Pwm::init_output_compare` and `OutputMode`.
 pub struct Pwm<'a, T>(pub &'a T)
 where
     T: 'a;
 
+/// Counter alignment (CMS bits of TIMx_CR1)
+///
+/// In the center-aligned modes the counter counts up to ARR and back down to
+/// zero, so the output frequency is half the one obtained in edge-aligned mode
+/// for the same period.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Alignment {
+    /// Edge-aligned, the counter counts up
+    Edge = 0b00,
+    /// Center-aligned mode 1, compare flags are set while counting down
+    Center1 = 0b01,
+    /// Center-aligned mode 2, compare flags are set while counting up
+    Center2 = 0b10,
+    /// Center-aligned mode 3, compare flags are set while counting up and down
+    Center3 = 0b11,
+}
+
+/// Output polarity of a channel (CCxP bit of TIMx_CCER)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Polarity {
+    /// OCx is active high
+    ActiveHigh,
+    /// OCx is active low
+    ActiveLow,
+}
+
+/// Output compare mode of a channel (OCxM bits of TIMx_CCMRx)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputMode {
+    /// The comparison between CCRx and the counter has no effect on the output
+    Frozen = 0b000,
+    /// The output is set active when the counter matches CCRx
+    ActiveOnMatch = 0b001,
+    /// The output is set inactive when the counter matches CCRx
+    InactiveOnMatch = 0b010,
+    /// The output toggles when the counter matches CCRx
+    Toggle = 0b011,
+    /// The output is forced inactive
+    ForceInactive = 0b100,
+    /// The output is forced active
+    ForceActive = 0b101,
+    /// PWM mode 1, active while the counter is below CCRx when counting up
+    Pwm1 = 0b110,
+    /// PWM mode 2, inactive while the counter is below CCRx when counting up
+    Pwm2 = 0b111,
+}
+
+/// IMPLEMENTATION DETAIL
+///
+/// Duty cycle representation of a PWM timer (16 bits on TIM1, 32 bits on
+/// TIM2-4)
+pub trait Duty: Copy + Into<u32> {
+    /// IMPLEMENTATION DETAIL
+    fn from_u32(duty: u32) -> Self;
+}
+
+impl Duty for u16 {
+    fn from_u32(duty: u32) -> u16 {
+        u16(duty).unwrap_or(::core::u16::MAX)
+    }
+}
+
+impl Duty for u32 {
+    fn from_u32(duty: u32) -> u32 {
+        duty
+    }
+}
+
+/// PWM timer whose update events can be held off while several compare
+/// values are written
+pub trait HoldUpdates {
+    /// Sets (`true`) or clears (`false`) UDIS in TIMx_CR1
+    ///
+    /// While set, preloaded compare values aren't transferred to the shadow
+    /// registers, so values written in a row take effect on the same period.
+    fn hold_updates(&self, hold: bool);
+}
+
 macro_rules! impl_Pwm {
     ($TIM:ident, $APB:ident) => {
         impl<'a> Pwm<'a, $TIM>
@@ -78,11 +166,75 @@ macro_rules! impl_Pwm {
             ) {
                 let tim = self.0;
 
-                // enable AFIO, (DMA1), TIMx and GPIOx
+                // enable (DMA1)
                 if dma1.is_some() {
                     rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit());
                 }
 
+                self._init_pins(channel, gpioa, gpiob, gpioc, rcc);
+
+                // PWM mode 1, active high, preload enabled
+                if let Channel::_4 = channel {
+                    if tim.get_type_id() == TypeId::of::<TIM2>() {
+                        unimplemented!()
+                    }
+                }
+                self.set_output_mode(channel, OutputMode::Pwm1);
+                self.set_polarity(channel, Polarity::ActiveHigh);
+                self.set_preload(channel, true);
+
+                self._set_period(period);
+
+                if let Some(dma1) = dma1 {
+                    //  Update DMA request enable
+                    tim.dier.modify(|_, w| w.ude().set_bit());
+
+                    if tim.get_type_id() == TypeId::of::<TIM3>() {
+                        // TIM3_CH4/UP: Channel 5 (RM0368 9.3.3 Table 27)
+                        // 8-bit duty cycles into a 16-bit CCRx, the buffer is
+                        // replayed until the stream is disabled
+                        Dma1Stream2::configure(
+                            dma1,
+                            &dma::Config {
+                                channel: DmaChannel::_5,
+                                priority: Priority::Medium,
+                                direction: Direction::MemoryToPeripheral,
+                                msize: Size::Byte,
+                                psize: Size::HalfWord,
+                                minc: true,
+                                pinc: false,
+                                circular: true,
+                                interrupts: Flags::transfer_complete(),
+                            },
+                        );
+                    } else {
+                        unimplemented!()
+                    }
+                }
+
+                tim.cr1.write(|w| unsafe {
+                    w.cms()
+                        .bits(0b00)
+                        .dir()
+                        .bit(false)
+                        .opm()
+                        .bit(false)
+                        .cen()
+                        .set_bit()
+                });
+            }
+
+            fn _init_pins(
+                &self,
+                channel: Channel,
+                gpioa: &GPIOA,
+                gpiob: &GPIOB,
+                gpioc: &GPIOC,
+                rcc: &RCC,
+            ) {
+                let tim = self.0;
+
+                // enable TIMx and GPIOx
                 if tim.get_type_id() == TypeId::of::<TIM1>() {
                     rcc.apb2enr.modify(|_, w| w.tim1en().set_bit());
                 } else if tim.get_type_id() == TypeId::of::<TIM2>() {
@@ -213,78 +365,54 @@ macro_rules! impl_Pwm {
                         }
                     }
                 }
+            }
 
-                // PWM mode 1
-                match channel {
-                    Channel::_1 => {
-                        tim.ccmr1_output.modify(|_, w| unsafe {w.oc1pe().set_bit().oc1m().bits(0b110)});
-                        tim.ccer.modify(|_, w| {w.cc1p().clear_bit()});
-                    }
-                    Channel::_2 => {
-                        tim.ccmr1_output.modify(|_, w| unsafe {w.oc2pe().set_bit().oc2m().bits(0b110)});
-                        tim.ccer.modify(|_, w| {w.cc2p().clear_bit()});
-                    }
-                    Channel::_3 => {
-                        tim.ccmr2_output.modify(|_, w| unsafe {w.oc3pe().set_bit().oc3m().bits(0b110)});
-                        tim.ccer.modify(|_, w| {w.cc3p().clear_bit()});
-                    }
-                    Channel::_4 => {
-                        if tim.get_type_id() == TypeId::of::<TIM2>() {
-                            unimplemented!()
-                        }
-                        tim.ccmr2_output.modify(|_, w| unsafe {w.oc4pe().set_bit().oc4m().bits(0b110)});
-                        tim.ccer.modify(|_, w| {w.cc4p().clear_bit()});
-                    }
-                }
+            fn _set_period(&self, period: ::$APB::Ticks) {
+                let period = period.0;
 
-                self._set_period(period);
+                let psc = u16((period - 1) / (1 << 16)).unwrap();
+                self.0.psc.write(|w| unsafe{w.psc().bits(psc)});
 
-                if let Some(dma1) = dma1 {
-                    //  Update DMA request enable
-                    tim.dier.modify(|_, w| w.ude().set_bit());
+                let arr = u32(period / u32(psc + 1));
+                self.0.arr.write(|w| unsafe{w.bits(arr)});
+            }
 
-                    if tim.get_type_id() == TypeId::of::<TIM3>() {
-                        // TIM3_CH4/UP
-                        // chsel: Channel 5 (RM0368 9.3.3 Table 27)
-                        // pl: Medium priority
-                        // msize: Memory size = 8 bits
-                        // psize: Peripheral size = 16 bits
-                        // minc: Memory increment mode enabled
-                        // pinc: Peripheral increment mode disabled
-                        // circ: Circular mode disabled
-                        // dir: Transfer from memory to peripheral
-                        // tcie: Transfer complete interrupt enabled
-                        // en: Disabled
-                        dma1.s2cr.write(|w| unsafe {
-                            w.chsel()
-                                .bits(5)
-                                .pl()
-                                .bits(0b01)
-                                .msize()
-                                .bits(0b00)
-                                .psize()
-                                .bits(0b01)
-                                .minc()
-                                .set_bit()
-                                .circ()
-                                .set_bit()
-                                .pinc()
-                                .clear_bit()
-                                .dir()
-                                .bits(1)
-                                .tcie()
-                                .set_bit()
-                                .en()
-                                .clear_bit()
-                        });
-                    } else {
+            /// Initializes `channel` as an output compare generator
+            ///
+            /// Unlike `init` the channel is left in `mode` with preload
+            /// disabled, so new compare values written with `set_duty` take
+            /// effect immediately. The output still has to be `enable`d.
+            pub fn init_output_compare<P>(
+                &self,
+                period: P,
+                channel: Channel,
+                mode: OutputMode,
+                gpioa: &GPIOA,
+                gpiob: &GPIOB,
+                gpioc: &GPIOC,
+                rcc: &RCC,
+            ) where
+                P: Into<::$APB::Ticks>,
+            {
+                let tim = self.0;
+
+                if let Channel::_4 = channel {
+                    if tim.get_type_id() == TypeId::of::<TIM2>() {
                         unimplemented!()
                     }
                 }
 
+                self._init_pins(channel, gpioa, gpiob, gpioc, rcc);
+
+                self.set_output_mode(channel, mode);
+                self.set_polarity(channel, Polarity::ActiveHigh);
+                self.set_preload(channel, false);
+
+                self._set_period(period.into());
+
                 tim.cr1.write(|w| unsafe {
                     w.cms()
-                        .bits(0b00)
+                        .bits(Alignment::Edge as u8)
                         .dir()
                         .bit(false)
                         .opm()
@@ -294,14 +422,66 @@ macro_rules! impl_Pwm {
                 });
             }
 
-            fn _set_period(&self, period: ::$APB::Ticks) {
-                let period = period.0;
+            /// Selects edge or center-aligned counting
+            ///
+            /// The counter is briefly stopped while the alignment is changed
+            /// because CMS can't be modified while the counter is enabled
+            pub fn set_alignment(&self, alignment: Alignment) {
+                let tim = self.0;
 
-                let psc = u16((period - 1) / (1 << 16)).unwrap();
-                self.0.psc.write(|w| unsafe{w.psc().bits(psc)});
+                let cen = tim.cr1.read().cen().bit_is_set();
+                tim.cr1.modify(|_, w| w.cen().clear_bit());
+                tim.cr1.modify(|_, w| unsafe { w.cms().bits(alignment as u8) });
+                if cen {
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+            }
 
-                let arr = u32(period / u32(psc + 1));
-                self.0.arr.write(|w| unsafe{w.bits(arr)});
+            /// Returns the current counter alignment
+            pub fn get_alignment(&self) -> Alignment {
+                match self.0.cr1.read().cms().bits() {
+                    0b00 => Alignment::Edge,
+                    0b01 => Alignment::Center1,
+                    0b10 => Alignment::Center2,
+                    _ => Alignment::Center3,
+                }
+            }
+
+            /// Sets the output polarity of `channel`
+            pub fn set_polarity(&self, channel: Channel, polarity: Polarity) {
+                let low = polarity == Polarity::ActiveLow;
+                match channel {
+                    Channel::_1 => self.0.ccer.modify(|_, w| w.cc1p().bit(low)),
+                    Channel::_2 => self.0.ccer.modify(|_, w| w.cc2p().bit(low)),
+                    Channel::_3 => self.0.ccer.modify(|_, w| w.cc3p().bit(low)),
+                    Channel::_4 => self.0.ccer.modify(|_, w| w.cc4p().bit(low)),
+                }
+            }
+
+            /// Sets the output compare mode of `channel`
+            pub fn set_output_mode(&self, channel: Channel, mode: OutputMode) {
+                let mode = mode as u8;
+                unsafe {
+                    match channel {
+                        Channel::_1 => self.0.ccmr1_output.modify(|_, w| w.oc1m().bits(mode)),
+                        Channel::_2 => self.0.ccmr1_output.modify(|_, w| w.oc2m().bits(mode)),
+                        Channel::_3 => self.0.ccmr2_output.modify(|_, w| w.oc3m().bits(mode)),
+                        Channel::_4 => self.0.ccmr2_output.modify(|_, w| w.oc4m().bits(mode)),
+                    }
+                }
+            }
+
+            /// Enables or disables the CCRx preload of `channel`
+            ///
+            /// With preload enabled a new compare value only takes effect on
+            /// the next update event, which avoids glitches in PWM mode.
+            pub fn set_preload(&self, channel: Channel, enabled: bool) {
+                match channel {
+                    Channel::_1 => self.0.ccmr1_output.modify(|_, w| w.oc1pe().bit(enabled)),
+                    Channel::_2 => self.0.ccmr1_output.modify(|_, w| w.oc2pe().bit(enabled)),
+                    Channel::_3 => self.0.ccmr2_output.modify(|_, w| w.oc3pe().bit(enabled)),
+                    Channel::_4 => self.0.ccmr2_output.modify(|_, w| w.oc4pe().bit(enabled)),
+                }
             }
 
             /// Uses `buffer` to continuously change the duty cycle on every period
@@ -317,31 +497,31 @@ macro_rules! impl_Pwm {
                 let tim3 = self.0;
 
                 if tim3.get_type_id() == TypeId::of::<TIM3>() {
-                    if dma1.s2cr.read().en().bit_is_set() {
-                        return Err(dma::Error::InUse);
-                    }
-
-                    let buffer: &[u8] = buffer.lock();
-
-                    dma1.s2ndtr.write(|w| unsafe { w.ndt().bits(u16(buffer.len()).unwrap()) });
-                    dma1.s2par.write(|w| unsafe {
-                        match channel {
-                            Channel::_1 => w.bits(&tim3.ccr1 as *const _ as u32),
-                            Channel::_2 => w.bits(&tim3.ccr2 as *const _ as u32),
-                            Channel::_3 => w.bits(&tim3.ccr3 as *const _ as u32),
-                            Channel::_4 => w.bits(&tim3.ccr4 as *const _ as u32),
-                        }
-                    });
-                    dma1.s2m0ar.write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
-                    dma1.s2cr.modify(|_, w| w.en().set_bit());
-
-                    Ok(())
-
+                    let ccr = match channel {
+                        Channel::_1 => &tim3.ccr1 as *const _ as u32,
+                        Channel::_2 => &tim3.ccr2 as *const _ as u32,
+                        Channel::_3 => &tim3.ccr3 as *const _ as u32,
+                        Channel::_4 => &tim3.ccr4 as *const _ as u32,
+                    };
+                    let len = {
+                        let duties: &[u8] = &*buffer.borrow();
+                        duties.len()
+                    };
+
+                    buffer.start::<u8>(dma1, ccr, len)
                 } else {
                     unimplemented!()
                 }
             }
         }
+
+        impl<'a> HoldUpdates for Pwm<'a, $TIM> {
+            fn hold_updates(&self, hold: bool) {
+                // RM0368 13.4.1
+                // UDIS[1]
+                self.0.cr1.modify(|_, w| w.udis().bit(hold));
+            }
+        }
     }
 }
 macro_rules! impl_halPwm {
@@ -388,7 +568,8 @@ macro_rules! impl_halPwm {
             }
 
             fn get_period(&self) -> ::$APB::Ticks {
-                ::$APB::Ticks(u32(self.0.psc.read().bits() * self.0.arr.read().bits()))
+                // inverse of `_set_period`
+                ::$APB::Ticks(u32((self.0.psc.read().bits() + 1) * self.0.arr.read().bits()))
             }
 
             fn set_duty(&self, channel: Channel, duty: u32) {
@@ -463,7 +644,8 @@ impl<'a> hal::Pwm for Pwm<'a, TIM1> {
     }
 
     fn get_period(&self) -> ::apb2::Ticks {
-        ::apb2::Ticks(u32(self.0.psc.read().bits() * self.0.arr.read().bits()))
+        // inverse of `_set_period`
+        ::apb2::Ticks(u32((self.0.psc.read().bits() + 1) * self.0.arr.read().bits()))
     }
 
     fn set_duty(&self, channel: Channel, duty: u16) {
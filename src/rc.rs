@@ -0,0 +1,395 @@
+//! RC receiver decoding
+//!
+//! # PPM
+//!
+//! A PPM stream carries up to 16 channels as the time between consecutive
+//! rising edges, followed by a long sync gap. `PpmDecoder` consumes the edge
+//! timestamps returned by `Capture::capture` and yields a `PpmFrame` after
+//! every sync gap.
+//!
+//! # SBUS
+//!
+//! SBUS is a 100 kbaud, 8E2, inverted serial protocol. The F4 USARTs can't
+//! invert their RX input so an external inverter (e.g. a single transistor)
+//! is required. Configure the `Serial` with a baud rate of 100 kHz and
+//! `set_format(Parity::Even, StopBits::_2)`, then feed every received byte to
+//! a `SbusDecoder`.
+//!
+//! Both decoders are plain state machines and don't touch any peripheral.
+
+use time::Microseconds;
+
+/// Maximum number of channels in a PPM frame
+pub const PPM_MAX_CHANNELS: usize = 16;
+
+/// PPM decoder configuration
+#[derive(Clone, Copy, Debug)]
+pub struct PpmConfig {
+    /// Capture counter ticks per microsecond
+    pub ticks_per_us: u32,
+    /// Mask applied to timestamp differences, `0xffff` for 16-bit timers
+    pub counter_mask: u32,
+    /// Any interval at least this long is a sync gap
+    pub sync_gap: Microseconds,
+    /// Shortest valid channel pulse
+    pub min_pulse: Microseconds,
+    /// Longest valid channel pulse
+    pub max_pulse: Microseconds,
+    /// Frames with fewer channels are dropped
+    pub min_channels: usize,
+}
+
+impl Default for PpmConfig {
+    /// 1 MHz capture counter on a 16-bit timer, 2.7 ms sync gap, 0.75 -
+    /// 2.25 ms pulses, at least 4 channels
+    fn default() -> Self {
+        PpmConfig {
+            ticks_per_us: 1,
+            counter_mask: 0xffff,
+            sync_gap: Microseconds(2_700),
+            min_pulse: Microseconds(750),
+            max_pulse: Microseconds(2_250),
+            min_channels: 4,
+        }
+    }
+}
+
+/// A complete PPM frame
+#[derive(Clone, Copy, Debug)]
+pub struct PpmFrame {
+    pulses: [u16; PPM_MAX_CHANNELS],
+    len: usize,
+}
+
+impl PpmFrame {
+    /// Channel pulse widths, in microseconds
+    pub fn channels(&self) -> &[u16] {
+        &self.pulses[..self.len]
+    }
+}
+
+/// PPM decoder
+pub struct PpmDecoder {
+    config: PpmConfig,
+    last: Option<u32>,
+    synced: bool,
+    frame: PpmFrame,
+}
+
+impl PpmDecoder {
+    /// Creates a decoder that waits for the first sync gap
+    pub const fn new(config: PpmConfig) -> Self {
+        PpmDecoder {
+            config: config,
+            last: None,
+            synced: false,
+            frame: PpmFrame {
+                pulses: [0; PPM_MAX_CHANNELS],
+                len: 0,
+            },
+        }
+    }
+
+    /// Drops any partial frame and waits for the next sync gap, e.g. after
+    /// an overcapture
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.synced = false;
+        self.frame.len = 0;
+    }
+
+    /// Feeds the timestamp of a rising edge
+    ///
+    /// Returns the frame that ends with this edge's sync gap, if any. An
+    /// out-of-range pulse or too many channels drop the partial frame.
+    pub fn feed(&mut self, timestamp: u32) -> Option<PpmFrame> {
+        let last = self.last;
+        self.last = Some(timestamp);
+
+        let ticks = match last {
+            Some(last) => timestamp.wrapping_sub(last) & self.config.counter_mask,
+            None => return None,
+        };
+        let width = ticks / self.config.ticks_per_us;
+
+        if width >= self.config.sync_gap.0 {
+            let frame = if self.synced && self.frame.len >= self.config.min_channels {
+                Some(self.frame)
+            } else {
+                None
+            };
+            self.synced = true;
+            self.frame.len = 0;
+            frame
+        } else {
+            if self.synced {
+                if width >= self.config.min_pulse.0 && width <= self.config.max_pulse.0 &&
+                    self.frame.len < PPM_MAX_CHANNELS
+                {
+                    self.frame.pulses[self.frame.len] = width as u16;
+                    self.frame.len += 1;
+                } else {
+                    self.synced = false;
+                    self.frame.len = 0;
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Length of an SBUS frame, in bytes
+pub const SBUS_FRAME_LEN: usize = 25;
+
+/// Number of proportional channels in an SBUS frame
+pub const SBUS_CHANNELS: usize = 16;
+
+const SBUS_HEADER: u8 = 0x0f;
+
+/// A decoded SBUS frame
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SbusFrame {
+    /// 11-bit channel values, nominally 172 ..= 1811
+    pub channels: [u16; SBUS_CHANNELS],
+    /// Digital channel 17
+    pub ch17: bool,
+    /// Digital channel 18
+    pub ch18: bool,
+    /// The receiver lost this frame
+    pub frame_lost: bool,
+    /// The receiver is in failsafe
+    pub failsafe: bool,
+}
+
+impl SbusFrame {
+    /// Unpacks the 22 channel bytes and the flags byte of a frame
+    pub fn unpack(data: &[u8; 22], flags: u8) -> Self {
+        let mut channels = [0; SBUS_CHANNELS];
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        let mut bytes = data.iter();
+
+        // channels are packed LSB first as a little endian bit stream
+        for ch in channels.iter_mut() {
+            while bits < 11 {
+                acc |= u32::from(*bytes.next().unwrap()) << bits;
+                bits += 8;
+            }
+            *ch = (acc & 0x7ff) as u16;
+            acc >>= 11;
+            bits -= 11;
+        }
+
+        SbusFrame {
+            channels: channels,
+            ch17: flags & (1 << 0) != 0,
+            ch18: flags & (1 << 1) != 0,
+            frame_lost: flags & (1 << 2) != 0,
+            failsafe: flags & (1 << 3) != 0,
+        }
+    }
+}
+
+/// SBUS decoder
+pub struct SbusDecoder {
+    buffer: [u8; SBUS_FRAME_LEN],
+    len: usize,
+}
+
+impl SbusDecoder {
+    /// Creates a decoder that waits for a frame header
+    pub const fn new() -> Self {
+        SbusDecoder {
+            buffer: [0; SBUS_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Drops any partial frame, e.g. after a framing or parity error
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Feeds a received byte
+    ///
+    /// Returns the frame this byte completes, if any. Frames with a bad
+    /// footer are dropped and the decoder waits for the next header.
+    pub fn feed(&mut self, byte: u8) -> Option<SbusFrame> {
+        if self.len == 0 && byte != SBUS_HEADER {
+            return None;
+        }
+
+        self.buffer[self.len] = byte;
+        self.len += 1;
+
+        if self.len < SBUS_FRAME_LEN {
+            return None;
+        }
+        self.len = 0;
+
+        // SBUS2 receivers cycle the footer through 0x04, 0x14, 0x24 and 0x34
+        let footer = self.buffer[SBUS_FRAME_LEN - 1];
+        if footer != 0x00 && footer & 0xcf != 0x04 {
+            return None;
+        }
+
+        let mut data = [0; 22];
+        data.copy_from_slice(&self.buffer[1..23]);
+        Some(SbusFrame::unpack(&data, self.buffer[23]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 channels: 172, 992, 1811, 1024, 0, 2047, 1, 1000, 1500, 300, 811,
+    // 1234, 555, 1700, 999, 173
+    const SBUS_DATA: [u8; 22] = [
+        0xac, 0x00, 0xdf, 0xc4, 0x01, 0x08, 0x80, 0xff, 0x07, 0x00, 0x7d, 0xdc,
+        0x65, 0xc9, 0xca, 0xa4, 0xb9, 0x22, 0x52, 0x9f, 0xaf, 0x15,
+    ];
+
+    const SBUS_CHANNEL_VALUES: [u16; SBUS_CHANNELS] = [
+        172, 992, 1811, 1024, 0, 2047, 1, 1000, 1500, 300, 811, 1234, 555, 1700,
+        999, 173,
+    ];
+
+    fn sbus_frame(flags: u8, footer: u8) -> [u8; SBUS_FRAME_LEN] {
+        let mut frame = [0; SBUS_FRAME_LEN];
+        frame[0] = SBUS_HEADER;
+        frame[1..23].copy_from_slice(&SBUS_DATA);
+        frame[23] = flags;
+        frame[24] = footer;
+        frame
+    }
+
+    fn feed_sbus(decoder: &mut SbusDecoder, bytes: &[u8]) -> Option<SbusFrame> {
+        let mut frame = None;
+        for byte in bytes {
+            if let Some(f) = decoder.feed(*byte) {
+                assert!(frame.is_none());
+                frame = Some(f);
+            }
+        }
+        frame
+    }
+
+    /// Feeds the rising edges of PPM pulses, as captured by a 16-bit timer
+    /// running at 1 MHz, starting at `start`
+    fn feed_ppm(
+        decoder: &mut PpmDecoder,
+        start: u32,
+        intervals: &[u32],
+    ) -> (u32, Option<PpmFrame>) {
+        let mut timestamp = start;
+        let mut frame = None;
+        for interval in intervals {
+            timestamp = (timestamp + *interval) & 0xffff;
+            if let Some(f) = decoder.feed(timestamp) {
+                assert!(frame.is_none());
+                frame = Some(f);
+            }
+        }
+        (timestamp, frame)
+    }
+
+    #[test]
+    fn sbus_unpack() {
+        let frame = SbusFrame::unpack(&SBUS_DATA, 0);
+        assert_eq!(frame.channels, SBUS_CHANNEL_VALUES);
+        assert!(!frame.ch17 && !frame.ch18 && !frame.frame_lost && !frame.failsafe);
+
+        let frame = SbusFrame::unpack(&SBUS_DATA, 0b0100);
+        assert!(frame.frame_lost && !frame.failsafe);
+
+        let frame = SbusFrame::unpack(&SBUS_DATA, 0b1000);
+        assert!(!frame.frame_lost && frame.failsafe);
+
+        let frame = SbusFrame::unpack(&SBUS_DATA, 0b0011);
+        assert!(frame.ch17 && frame.ch18 && !frame.frame_lost && !frame.failsafe);
+    }
+
+    #[test]
+    fn sbus_frames() {
+        let mut decoder = SbusDecoder::new();
+
+        // garbage before the first header is skipped
+        assert!(feed_sbus(&mut decoder, &[0x00, 0xff, 0x12]).is_none());
+
+        let frame = feed_sbus(&mut decoder, &sbus_frame(0b1100, 0x00)).unwrap();
+        assert_eq!(frame.channels, SBUS_CHANNEL_VALUES);
+        assert!(frame.frame_lost && frame.failsafe);
+
+        for footer in &[0x04, 0x14, 0x24, 0x34] {
+            assert!(feed_sbus(&mut decoder, &sbus_frame(0, *footer)).is_some());
+        }
+    }
+
+    #[test]
+    fn sbus_bad_footer() {
+        let mut decoder = SbusDecoder::new();
+
+        for footer in &[0x01, 0x0f, 0x44, 0x84, 0xf4, 0xff] {
+            assert!(feed_sbus(&mut decoder, &sbus_frame(0, *footer)).is_none());
+        }
+
+        // the decoder recovers on the next frame
+        assert!(feed_sbus(&mut decoder, &sbus_frame(0, 0x00)).is_some());
+    }
+
+    #[test]
+    fn ppm_frames() {
+        let mut decoder = PpmDecoder::new(PpmConfig::default());
+
+        // the partial frame before the first sync gap is dropped
+        let (t, frame) = feed_ppm(&mut decoder, 0, &[1_000, 1_500, 5_000]);
+        assert!(frame.is_none());
+
+        let (_, frame) = feed_ppm(
+            &mut decoder,
+            t,
+            &[1_000, 1_500, 2_000, 1_200, 800, 2_200, 10_000],
+        );
+        assert_eq!(
+            frame.unwrap().channels(),
+            &[1_000, 1_500, 2_000, 1_200, 800, 2_200]
+        );
+
+        // the counter wraps around in the middle of this frame
+        decoder.reset();
+        assert!(decoder.feed(0xffff - 5_000).is_none());
+        let (_, frame) = feed_ppm(
+            &mut decoder,
+            0xffff - 5_000,
+            &[4_000, 1_100, 1_900, 1_300, 1_700, 4_000],
+        );
+        assert_eq!(frame.unwrap().channels(), &[1_100, 1_900, 1_300, 1_700]);
+    }
+
+    #[test]
+    fn ppm_invalid_frames() {
+        let mut decoder = PpmDecoder::new(PpmConfig::default());
+        let (t, _) = feed_ppm(&mut decoder, 0, &[1_000, 5_000]);
+
+        // too few channels
+        let (t, frame) = feed_ppm(&mut decoder, t, &[1_000, 1_500, 2_000, 5_000]);
+        assert!(frame.is_none());
+
+        // out of range pulse
+        let (t, frame) = feed_ppm(
+            &mut decoder,
+            t,
+            &[1_000, 1_500, 500, 2_000, 1_200, 5_000],
+        );
+        assert!(frame.is_none());
+
+        // the decoder resyncs on the next frame
+        let (_, frame) = feed_ppm(
+            &mut decoder,
+            t,
+            &[1_000, 1_500, 2_000, 1_200, 5_000],
+        );
+        assert_eq!(frame.unwrap().channels(), &[1_000, 1_500, 2_000, 1_200]);
+    }
+}
@@ -48,10 +48,32 @@ pub enum Error {
     Noise,
     /// RX buffer overrun
     Overrun,
+    /// Parity check failed
+    Parity,
     #[doc(hidden)]
     _Extensible,
 }
 
+/// Parity control
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// Even parity
+    Even,
+    /// Odd parity
+    Odd,
+}
+
+/// Number of stop bits
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopBits {
+    /// 1 stop bit
+    _1 = 0b00,
+    /// 2 stop bits
+    _2 = 0b10,
+}
+
 /// Interrupt event
 pub enum Event {
     /// RX buffer Not Empty (new data available)
@@ -251,6 +273,33 @@ where
         });
     }
 
+    /// Changes the frame format to 8 data bits with `parity` and
+    /// `stop_bits`
+    ///
+    /// When parity is enabled the word length is extended to 9 bits so that
+    /// 8 data bits are still transferred. E.g. SBUS uses `Parity::Even` and
+    /// `StopBits::_2` (8E2).
+    pub fn set_format(&self, parity: Parity, stop_bits: StopBits) {
+        let usart = self.0;
+
+        // the frame format can only be changed while the USART is disabled
+        usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+        usart.cr2.modify(|_, w| unsafe { w.stop().bits(stop_bits as u8) });
+
+        // RM0368 19.6.4
+        // m: 9 data bits (8 data + parity) when parity is enabled
+        // pce: Parity control enable
+        // ps: Parity selection, 0 = even, 1 = odd
+        usart.cr1.modify(|_, w| match parity {
+            Parity::None => w.m().clear_bit().pce().clear_bit(),
+            Parity::Even => w.m().set_bit().pce().set_bit().ps().clear_bit(),
+            Parity::Odd => w.m().set_bit().pce().set_bit().ps().set_bit(),
+        });
+
+        usart.cr1.modify(|_, w| w.ue().set_bit());
+    }
+
     /// Starts listening for an interrupt `event`
     pub fn listen(&self, event: Event) {
         let usart = self.0;
@@ -290,6 +339,10 @@ where
             Err(nb::Error::Other(Error::Noise))
         } else if sr.fe().bit_is_set() {
             Err(nb::Error::Other(Error::Framing))
+        } else if sr.pe().bit_is_set() {
+            // NOTE reading DR clears the parity error flag
+            unsafe { ptr::read_volatile(&usart2.dr as *const _ as *const u8) };
+            Err(nb::Error::Other(Error::Parity))
         } else if sr.rxne().bit_is_set() {
             // NOTE(read_volatile) the register is 9 bits big but we'll only
             // work with the first 8 bits
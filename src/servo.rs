@@ -0,0 +1,382 @@
+//! RC servo driver
+//!
+//! Hobby servos expect a pulse every 20 ms (50 Hz) whose width, usually
+//! between 1 and 2 ms, selects the position. A `Servo` drives one channel of a
+//! `Pwm` that has been initialized with a period of `PERIOD`, e.g.
+//!
+//! ``` ignore
+//! pwm.init(servo::PERIOD, Channel::_1, None, gpioa, gpiob, gpioc, rcc);
+//! let mut servo = Servo::new(&pwm, Channel::_1, ServoConfig::default());
+//! servo.enable();
+//! servo.set_angle(45.0);
+//! ```
+//!
+//! Position changes can optionally be rate limited. In that case `update` has
+//! to be called periodically (e.g. from the timer update interrupt) to move the
+//! pulse towards the target.
+//!
+//! Pulse widths are converted to duty cycles using the period and the maximum
+//! duty cycle read back from the `Pwm`.
+
+use hal;
+
+use pwm::{Duty, HoldUpdates};
+use time::{Microseconds, Milliseconds};
+use timer::Channel;
+
+/// Standard servo frame period (50 Hz)
+pub const PERIOD: Milliseconds = Milliseconds(20);
+
+/// Servo configuration
+#[derive(Clone, Copy, Debug)]
+pub struct ServoConfig {
+    /// Pulse width at the minimum angle
+    pub min_pulse: Microseconds,
+    /// Pulse width at the maximum angle
+    pub max_pulse: Microseconds,
+    /// Travel between the minimum and maximum pulse widths, in degrees
+    pub range: f32,
+    /// Offset added to every pulse width, in microseconds
+    pub trim: i32,
+    /// Maximum pulse width change per call to `update`, in microseconds.
+    /// `None` moves to a new position immediately
+    pub max_step: Option<u32>,
+}
+
+impl Default for ServoConfig {
+    /// 1-2 ms pulses over 180 degrees, no trim and no rate limit
+    fn default() -> Self {
+        ServoConfig {
+            min_pulse: Microseconds(1_000),
+            max_pulse: Microseconds(2_000),
+            range: 180.0,
+            trim: 0,
+            max_step: None,
+        }
+    }
+}
+
+impl ServoConfig {
+    /// Returns the pulse width, in microseconds, for `angle` degrees relative
+    /// to the center position
+    ///
+    /// The result, including trim, is clamped to the configured pulse range.
+    pub fn angle_to_pulse(&self, angle: f32) -> u32 {
+        let min = self.min_pulse.0 as f32;
+        let max = self.max_pulse.0 as f32;
+        let center = (min + max) / 2.0 + self.trim as f32;
+
+        let pulse = center + angle * (max - min) / self.range;
+        if pulse < min {
+            self.min_pulse.0
+        } else if pulse > max {
+            self.max_pulse.0
+        } else {
+            pulse as u32
+        }
+    }
+
+    /// Returns the angle, in degrees relative to the center position, that
+    /// corresponds to `pulse` microseconds
+    pub fn pulse_to_angle(&self, pulse: u32) -> f32 {
+        let min = self.min_pulse.0 as f32;
+        let max = self.max_pulse.0 as f32;
+        let center = (min + max) / 2.0 + self.trim as f32;
+
+        (pulse as f32 - center) * self.range / (max - min)
+    }
+}
+
+/// Servo connected to a `channel` of `pwm`
+pub struct Servo<'a, P>
+where
+    P: 'a + hal::Pwm<Channel = Channel>,
+    P::Duty: Duty,
+    P::Time: Into<Microseconds>,
+{
+    pwm: &'a P,
+    channel: Channel,
+    config: ServoConfig,
+    pulse: u32,
+    target: u32,
+}
+
+impl<'a, P> Servo<'a, P>
+where
+    P: 'a + hal::Pwm<Channel = Channel>,
+    P::Duty: Duty,
+    P::Time: Into<Microseconds>,
+{
+    /// Creates a servo centered at 0 degrees
+    ///
+    /// NOTE `pwm` must already be initialized, usually with a period of
+    /// `PERIOD`
+    pub fn new(pwm: &'a P, channel: Channel, config: ServoConfig) -> Self {
+        let center = config.angle_to_pulse(0.0);
+        let servo = Servo {
+            pwm: pwm,
+            channel: channel,
+            config: config,
+            pulse: center,
+            target: center,
+        };
+        servo.write(center);
+        servo
+    }
+
+    /// Starts sending pulses
+    pub fn enable(&self) {
+        self.pwm.enable(self.channel);
+    }
+
+    /// Stops sending pulses, most servos go limp
+    pub fn disable(&self) {
+        self.pwm.disable(self.channel);
+    }
+
+    /// Returns the configuration
+    pub fn config(&self) -> &ServoConfig {
+        &self.config
+    }
+
+    /// Changes the trim, in microseconds
+    pub fn set_trim(&mut self, trim: i32) {
+        self.config.trim = trim;
+    }
+
+    /// Changes the rate limit, in microseconds per `update`
+    pub fn set_max_step(&mut self, max_step: Option<u32>) {
+        self.config.max_step = max_step;
+    }
+
+    /// Moves to `angle` degrees relative to the center position
+    pub fn set_angle(&mut self, angle: f32) {
+        let pulse = self.config.angle_to_pulse(angle);
+        self.set_pulse(Microseconds(pulse));
+    }
+
+    /// Moves to a raw pulse width, clamped to the configured range
+    pub fn set_pulse(&mut self, pulse: Microseconds) {
+        let pulse = if pulse.0 < self.config.min_pulse.0 {
+            self.config.min_pulse.0
+        } else if pulse.0 > self.config.max_pulse.0 {
+            self.config.max_pulse.0
+        } else {
+            pulse.0
+        };
+
+        self.target = pulse;
+        if self.config.max_step.is_none() {
+            self.pulse = pulse;
+            self.write(pulse);
+        }
+    }
+
+    /// Returns the current angle, in degrees
+    pub fn get_angle(&self) -> f32 {
+        self.config.pulse_to_angle(self.pulse)
+    }
+
+    /// Returns `true` when the servo has reached its target position
+    pub fn is_settled(&self) -> bool {
+        self.pulse == self.target
+    }
+
+    /// Steps the pulse width towards the target by at most `max_step`
+    ///
+    /// Returns `true` when the target has been reached
+    pub fn update(&mut self) -> bool {
+        let step = match self.config.max_step {
+            Some(step) => step,
+            None => return true,
+        };
+
+        if self.pulse < self.target {
+            self.pulse = if self.target - self.pulse > step {
+                self.pulse + step
+            } else {
+                self.target
+            };
+        } else if self.pulse > self.target {
+            self.pulse = if self.pulse - self.target > step {
+                self.pulse - step
+            } else {
+                self.target
+            };
+        }
+
+        let pulse = self.pulse;
+        self.write(pulse);
+        self.is_settled()
+    }
+
+    fn write(&self, pulse: u32) {
+        let period: Microseconds = self.pwm.get_period().into();
+        assert!(period.0 != 0, "PWM not initialized");
+
+        let max_duty: u32 = self.pwm.get_max_duty().into();
+        let duty = u64::from(pulse) * u64::from(max_duty) / u64::from(period.0);
+        self.pwm.set_duty(self.channel, P::Duty::from_u32(duty as u32));
+    }
+}
+
+/// Up to four servos connected to channels of the same timer
+pub struct ServoGroup<'a, P>
+where
+    P: 'a + hal::Pwm<Channel = Channel>,
+    P::Duty: Duty,
+    P::Time: Into<Microseconds>,
+{
+    pwm: &'a P,
+    /// Indexed by channel (CH1 = 0)
+    servos: [Option<Servo<'a, P>>; 4],
+}
+
+fn index(channel: Channel) -> usize {
+    match channel {
+        Channel::_1 => 0,
+        Channel::_2 => 1,
+        Channel::_3 => 2,
+        Channel::_4 => 3,
+    }
+}
+
+impl<'a, P> ServoGroup<'a, P>
+where
+    P: 'a + hal::Pwm<Channel = Channel>,
+    P::Duty: Duty,
+    P::Time: Into<Microseconds>,
+{
+    /// Creates a servo on each of the listed channels of `pwm`, each with its
+    /// own configuration
+    ///
+    /// Only list channels the timer can output, e.g. TIM2 has no CH4 pin.
+    pub fn new(pwm: &'a P, servos: &[(Channel, ServoConfig)]) -> Self {
+        let mut group = ServoGroup {
+            pwm: pwm,
+            servos: [None, None, None, None],
+        };
+
+        for &(channel, config) in servos {
+            let slot = &mut group.servos[index(channel)];
+            assert!(slot.is_none(), "duplicate servo channel");
+            *slot = Some(Servo::new(pwm, channel, config));
+        }
+
+        group
+    }
+
+    /// Returns the servo on `channel`, if any
+    pub fn servo(&self, channel: Channel) -> Option<&Servo<'a, P>> {
+        self.servos[index(channel)].as_ref()
+    }
+
+    /// Returns the servo on `channel`, if any
+    pub fn servo_mut(&mut self, channel: Channel) -> Option<&mut Servo<'a, P>> {
+        self.servos[index(channel)].as_mut()
+    }
+
+    /// Starts sending pulses on all channels
+    pub fn enable(&self) {
+        for servo in self.servos.iter().filter_map(|s| s.as_ref()) {
+            servo.enable();
+        }
+    }
+
+    /// Stops sending pulses on all channels
+    pub fn disable(&self) {
+        for servo in self.servos.iter().filter_map(|s| s.as_ref()) {
+            servo.disable();
+        }
+    }
+
+    /// Moves all servos, `angles` is indexed by channel and the angles of
+    /// channels without a servo are ignored
+    ///
+    /// Update events are held off while the compare values are written, so
+    /// all servos move on the same period. The channels must have their
+    /// compare preload enabled, as `Pwm::init` does.
+    pub fn set_angles(&mut self, angles: [f32; 4])
+    where
+        P: HoldUpdates,
+    {
+        self.pwm.hold_updates(true);
+        for (servo, angle) in self.servos.iter_mut().zip(angles.iter()) {
+            if let Some(ref mut servo) = *servo {
+                servo.set_angle(*angle);
+            }
+        }
+        self.pwm.hold_updates(false);
+    }
+
+    /// Steps all rate limited servos towards their targets
+    ///
+    /// Update events are held off as in `set_angles`, so all servos step on
+    /// the same period. Returns `true` when every servo has reached its
+    /// target
+    pub fn update(&mut self) -> bool
+    where
+        P: HoldUpdates,
+    {
+        let mut settled = true;
+        self.pwm.hold_updates(true);
+        for servo in self.servos.iter_mut().filter_map(|s| s.as_mut()) {
+            settled &= servo.update();
+        }
+        self.pwm.hold_updates(false);
+        settled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angle_to_pulse_maps_the_range() {
+        let config = ServoConfig::default();
+
+        assert_eq!(config.angle_to_pulse(0.0), 1_500);
+        assert_eq!(config.angle_to_pulse(45.0), 1_750);
+        assert_eq!(config.angle_to_pulse(90.0), 2_000);
+        assert_eq!(config.angle_to_pulse(-90.0), 1_000);
+
+        // clamped to the pulse range
+        assert_eq!(config.angle_to_pulse(120.0), 2_000);
+        assert_eq!(config.angle_to_pulse(-120.0), 1_000);
+    }
+
+    #[test]
+    fn angle_to_pulse_with_trim() {
+        let config = ServoConfig {
+            min_pulse: Microseconds(500),
+            max_pulse: Microseconds(2_500),
+            range: 270.0,
+            trim: 100,
+            max_step: None,
+        };
+
+        // 2000 us over 270 degrees, centered at 1500 + 100 us
+        assert_eq!(config.angle_to_pulse(0.0), 1_600);
+        assert_eq!(config.angle_to_pulse(-27.0), 1_400);
+        // the trim doesn't move the limits
+        assert_eq!(config.angle_to_pulse(135.0), 2_500);
+        assert_eq!(config.angle_to_pulse(-135.0), 600);
+        assert_eq!(config.angle_to_pulse(-160.0), 500);
+    }
+
+    #[test]
+    fn pulse_to_angle_inverts_angle_to_pulse() {
+        let config = ServoConfig {
+            trim: -50,
+            ..ServoConfig::default()
+        };
+
+        assert_eq!(config.pulse_to_angle(1_450), 0.0);
+        assert_eq!(config.pulse_to_angle(1_950), 90.0);
+        for &angle in &[-72.0, -45.0, 0.0, 18.0, 81.0] {
+            let pulse = config.angle_to_pulse(angle);
+            assert_eq!(config.pulse_to_angle(pulse), angle);
+        }
+    }
+}
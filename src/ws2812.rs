@@ -0,0 +1,390 @@
+//! WS2812 / SK6812 addressable LED strips
+//!
+//! Every bit of a frame is sent as one 800 kHz PWM period whose high time
+//! encodes a zero or a one. The duty cycles are streamed into the compare
+//! register of a TIM3 channel by DMA1 stream 2 (TIM3_UP request), followed by a
+//! run of zero duty cycles that keeps the line low for the reset gap.
+//!
+//! # TIM3
+//!
+//! - CH1 = PA6
+//! - CH2 = PC7
+//! - CH3 = PB0
+//! - CH4 = PB1
+//!
+//! The encoder functions don't touch any peripheral and can be checked on the
+//! host.
+
+use core::cell::Cell;
+use core::marker::Unsize;
+
+use hal::prelude::*;
+use nb;
+use stm32f40x::{DMA1, TIM3, GPIOA, GPIOB, GPIOC, RCC};
+
+use dma::{self, Buffer, Dma1Stream2};
+use pwm::Pwm;
+use time::Hertz;
+use timer::Channel;
+
+/// Bit rate of the LED data line
+pub const BIT_RATE: Hertz = Hertz(800_000);
+
+/// Number of duty cycle slots used to encode one RGB LED
+pub const RGB_SLOTS: usize = 24;
+
+/// Number of duty cycle slots used to encode one RGBW LED
+pub const RGBW_SLOTS: usize = 32;
+
+/// RGB color
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rgb {
+    /// Red
+    pub r: u8,
+    /// Green
+    pub g: u8,
+    /// Blue
+    pub b: u8,
+}
+
+/// RGBW color, for SK6812 RGBW strips
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rgbw {
+    /// Red
+    pub r: u8,
+    /// Green
+    pub g: u8,
+    /// Blue
+    pub b: u8,
+    /// White
+    pub w: u8,
+}
+
+/// Line timing expressed in duty cycle units of an 800 kHz PWM
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Timing {
+    /// High time of a zero bit
+    pub t0h: u8,
+    /// High time of a one bit
+    pub t1h: u8,
+    /// Number of zero duty periods appended to a frame (1.25 us each)
+    pub reset_slots: usize,
+}
+
+impl Timing {
+    /// WS2812B timing: T0H = 0.4 us, T1H = 0.8 us, reset > 50 us
+    ///
+    /// `max_duty` is the duty cycle of a full 1.25 us period
+    pub fn ws2812(max_duty: u8) -> Self {
+        Timing {
+            t0h: scale(max_duty, 400),
+            t1h: scale(max_duty, 800),
+            reset_slots: 48,
+        }
+    }
+
+    /// SK6812 timing: T0H = 0.3 us, T1H = 0.6 us, reset > 80 us
+    ///
+    /// `max_duty` is the duty cycle of a full 1.25 us period
+    pub fn sk6812(max_duty: u8) -> Self {
+        Timing {
+            t0h: scale(max_duty, 300),
+            t1h: scale(max_duty, 600),
+            reset_slots: 72,
+        }
+    }
+}
+
+/// `max_duty * ns / 1250`, rounded to nearest
+fn scale(max_duty: u8, ns: u32) -> u8 {
+    ((u32::from(max_duty) * ns + 625) / 1250) as u8
+}
+
+/// Number of duty cycle slots a frame of `leds` LEDs of `slots_per_led`
+/// slots each takes, including the reset gap
+pub fn frame_len(leds: usize, slots_per_led: usize, timing: &Timing) -> usize {
+    leds * slots_per_led + timing.reset_slots
+}
+
+/// Encodes `byte`, MSB first, into the first 8 slots of `out`
+pub fn encode_byte(byte: u8, timing: &Timing, out: &mut [u8]) {
+    for (i, slot) in out[..8].iter_mut().enumerate() {
+        *slot = if byte & (0x80 >> i) != 0 {
+            timing.t1h
+        } else {
+            timing.t0h
+        };
+    }
+}
+
+/// Encodes `pixels` in GRB order followed by the reset gap
+///
+/// Returns the number of slots written
+///
+/// # Panics
+///
+/// Panics if `out` is shorter than `frame_len(pixels.len(), RGB_SLOTS, timing)`
+pub fn encode_rgb(pixels: &[Rgb], timing: &Timing, out: &mut [u8]) -> usize {
+    let len = frame_len(pixels.len(), RGB_SLOTS, timing);
+    assert!(out.len() >= len, "frame buffer too small");
+
+    for (p, chunk) in pixels.iter().zip(out.chunks_mut(RGB_SLOTS)) {
+        encode_byte(p.g, timing, &mut chunk[0..]);
+        encode_byte(p.r, timing, &mut chunk[8..]);
+        encode_byte(p.b, timing, &mut chunk[16..]);
+    }
+
+    reset(&mut out[pixels.len() * RGB_SLOTS..len]);
+    len
+}
+
+/// Encodes `pixels` in GRBW order followed by the reset gap
+///
+/// Returns the number of slots written
+///
+/// # Panics
+///
+/// Panics if `out` is shorter than `frame_len(pixels.len(), RGBW_SLOTS, timing)`
+pub fn encode_rgbw(pixels: &[Rgbw], timing: &Timing, out: &mut [u8]) -> usize {
+    let len = frame_len(pixels.len(), RGBW_SLOTS, timing);
+    assert!(out.len() >= len, "frame buffer too small");
+
+    for (p, chunk) in pixels.iter().zip(out.chunks_mut(RGBW_SLOTS)) {
+        encode_byte(p.g, timing, &mut chunk[0..]);
+        encode_byte(p.r, timing, &mut chunk[8..]);
+        encode_byte(p.b, timing, &mut chunk[16..]);
+        encode_byte(p.w, timing, &mut chunk[24..]);
+    }
+
+    reset(&mut out[pixels.len() * RGBW_SLOTS..len]);
+    len
+}
+
+fn reset(slots: &mut [u8]) {
+    for slot in slots {
+        *slot = 0;
+    }
+}
+
+/// A pair of frame buffers: one is encoded by software while the DMA sends
+/// the other one
+pub struct FrameBuffers<B> {
+    buffers: [Buffer<B, Dma1Stream2>; 2],
+    /// Index of the buffer that is sent next
+    back: Cell<usize>,
+    /// Number of valid slots in the back buffer
+    len: Cell<usize>,
+}
+
+impl<B> FrameBuffers<B> {
+    /// Creates the frame buffers from two (equally sized) arrays
+    pub const fn new(a: B, b: B) -> Self {
+        FrameBuffers {
+            buffers: [Buffer::new(a), Buffer::new(b)],
+            back: Cell::new(0),
+            len: Cell::new(0),
+        }
+    }
+}
+
+/// LED strip driven by a channel of TIM3
+pub struct Ws2812<'a> {
+    pwm: Pwm<'a, TIM3>,
+    channel: Channel,
+    timing: Timing,
+}
+
+impl<'a> Ws2812<'a> {
+    /// Creates a driver that will use `channel` of `tim3`
+    pub fn new(tim3: &'a TIM3, channel: Channel) -> Self {
+        Ws2812 {
+            pwm: Pwm(tim3),
+            channel: channel,
+            timing: Timing { t0h: 0, t1h: 0, reset_slots: 0 },
+        }
+    }
+
+    /// Initializes TIM3 at `BIT_RATE` and DMA1 stream 2 for one-shot frame
+    /// transfers, using WS2812B timing
+    ///
+    /// Call `set_timing` afterwards to drive SK6812 strips.
+    pub fn init(&mut self, dma1: &DMA1, gpioa: &GPIOA, gpiob: &GPIOB, gpioc: &GPIOC, rcc: &RCC) {
+        self.pwm.init(BIT_RATE.invert(), self.channel, Some(dma1), gpioa, gpiob, gpioc, rcc);
+
+        // frames are sent once, the line idles low after the reset gap
+        Dma1Stream2::set_circular(dma1, false);
+
+        let max_duty = self.pwm.get_max_duty();
+        assert!(max_duty <= 0xff, "timer clock too fast for 8-bit duty cycles");
+
+        self.timing = Timing::ws2812(max_duty as u8);
+        self.pwm.set_duty(self.channel, 0);
+        self.pwm.enable(self.channel);
+    }
+
+    /// Returns the line timing
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Changes the line timing
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// Encodes `pixels` into the back buffer of `frames`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the back buffer is still locked by a transfer
+    pub fn encode_rgb<B>(&self, frames: &FrameBuffers<B>, pixels: &[Rgb])
+    where
+        B: Unsize<[u8]>,
+    {
+        let mut out = frames.buffers[frames.back.get()].borrow_mut();
+        frames.len.set(encode_rgb(pixels, &self.timing, &mut *out));
+    }
+
+    /// Encodes `pixels` into the back buffer of `frames`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the back buffer is still locked by a transfer
+    pub fn encode_rgbw<B>(&self, frames: &FrameBuffers<B>, pixels: &[Rgbw])
+    where
+        B: Unsize<[u8]>,
+    {
+        let mut out = frames.buffers[frames.back.get()].borrow_mut();
+        frames.len.set(encode_rgbw(pixels, &self.timing, &mut *out));
+    }
+
+    /// Sends the back buffer of `frames` and swaps the buffers
+    ///
+    /// Returns `WouldBlock` while the previous frame is still being sent. Does
+    /// nothing if no frame has been encoded since the last `show`.
+    pub fn show<B>(&self, dma1: &DMA1, frames: &FrameBuffers<B>) -> nb::Result<(), dma::Error>
+    where
+        B: Unsize<[u8]>,
+    {
+        // NDTR = 0 would never complete and keep the buffer locked
+        if frames.len.get() == 0 {
+            return Ok(());
+        }
+
+        let front = frames.back.get() ^ 1;
+        frames.buffers[front].release(dma1)?;
+
+        let tim3 = self.pwm.0;
+        let ccr = match self.channel {
+            Channel::_1 => &tim3.ccr1 as *const _ as u32,
+            Channel::_2 => &tim3.ccr2 as *const _ as u32,
+            Channel::_3 => &tim3.ccr3 as *const _ as u32,
+            Channel::_4 => &tim3.ccr4 as *const _ as u32,
+        };
+        frames.buffers[frames.back.get()]
+            .start::<u8>(dma1, ccr, frames.len.get())
+            .map_err(nb::Error::Other)?;
+
+        frames.back.set(front);
+        frames.len.set(0);
+
+        Ok(())
+    }
+
+    /// Waits until the frame being sent has been released by the DMA
+    pub fn release<B>(&self, dma1: &DMA1, frames: &FrameBuffers<B>) -> nb::Result<(), dma::Error> {
+        frames.buffers[frames.back.get() ^ 1].release(dma1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMING: Timing = Timing {
+        t0h: 3,
+        t1h: 7,
+        reset_slots: 4,
+    };
+
+    #[test]
+    fn timings() {
+        // 84 MHz timer clock, 105 ticks per 1.25 us bit
+        assert_eq!(
+            Timing::ws2812(105),
+            Timing {
+                t0h: 34,
+                t1h: 67,
+                reset_slots: 48,
+            }
+        );
+        assert_eq!(
+            Timing::sk6812(105),
+            Timing {
+                t0h: 25,
+                t1h: 50,
+                reset_slots: 72,
+            }
+        );
+    }
+
+    #[test]
+    fn rgb() {
+        let mut out = [0xaa; 2 * RGB_SLOTS + 5];
+        let pixels = [
+            Rgb {
+                r: 0x80,
+                g: 0x01,
+                b: 0xff,
+            },
+            Rgb {
+                r: 0x00,
+                g: 0xa5,
+                b: 0x0f,
+            },
+        ];
+
+        let len = encode_rgb(&pixels, &TIMING, &mut out);
+        assert_eq!(len, 2 * RGB_SLOTS + 4);
+
+        // GRB, MSB first
+        assert_eq!(&out[0..8], &[3, 3, 3, 3, 3, 3, 3, 7]);
+        assert_eq!(&out[8..16], &[7, 3, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(&out[16..24], &[7; 8]);
+        assert_eq!(&out[24..32], &[7, 3, 7, 3, 3, 7, 3, 7]);
+        assert_eq!(&out[32..40], &[3; 8]);
+        assert_eq!(&out[40..48], &[3, 3, 3, 3, 7, 7, 7, 7]);
+
+        // reset gap, the rest of the buffer is left alone
+        assert_eq!(&out[48..52], &[0; 4]);
+        assert_eq!(out[52], 0xaa);
+    }
+
+    #[test]
+    fn rgbw() {
+        let mut out = [0xaa; RGBW_SLOTS + 4];
+        let pixels = [Rgbw {
+            r: 0x01,
+            g: 0x02,
+            b: 0x04,
+            w: 0x80,
+        }];
+
+        let len = encode_rgbw(&pixels, &TIMING, &mut out);
+        assert_eq!(len, RGBW_SLOTS + 4);
+
+        // GRBW, MSB first
+        assert_eq!(&out[0..8], &[3, 3, 3, 3, 3, 3, 7, 3]);
+        assert_eq!(&out[8..16], &[3, 3, 3, 3, 3, 3, 3, 7]);
+        assert_eq!(&out[16..24], &[3, 3, 3, 3, 3, 7, 3, 3]);
+        assert_eq!(&out[24..32], &[7, 3, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(&out[32..36], &[0; 4]);
+    }
+
+    #[test]
+    fn empty_frame_is_reset_only() {
+        let mut out = [0xaa; 4];
+        assert_eq!(encode_rgb(&[], &TIMING, &mut out), 4);
+        assert_eq!(out, [0; 4]);
+    }
+}